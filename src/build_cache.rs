@@ -0,0 +1,147 @@
+use crate::error;
+use crate::iff;
+use crate::spr;
+use crate::sprite_image;
+
+use anyhow::Context;
+
+const SPLIT_CACHE_FILE_NAME: &str = "split.json";
+const SPRITE_CHUNKS_DIRECTORY_NAME: &str = "sprites";
+
+pub fn cache_directory(source_directory: &std::path::Path, object_name: &str) -> std::path::PathBuf {
+    source_directory.join(format!("{} - cache", object_name))
+}
+
+// Every hash starts with the tool version, so that a new version never reuses what an older one built
+pub struct ContentHasher(sha2::Sha256);
+
+impl ContentHasher {
+    pub fn new() -> ContentHasher {
+        let mut hasher = ContentHasher(sha2::Sha256::default());
+        hasher.update(env!("CARGO_PKG_VERSION"));
+        hasher
+    }
+
+    // Each value is prefixed with its length so that consecutive values can't run into each other
+    pub fn update(&mut self, data: impl AsRef<[u8]>) {
+        use sha2::Digest;
+        let data = data.as_ref();
+        self.0.update(u64::try_from(data.len()).unwrap().to_le_bytes());
+        self.0.update(data);
+    }
+
+    pub fn update_file(&mut self, file_path: &std::path::Path) -> anyhow::Result<()> {
+        let data = std::fs::read(file_path).with_context(|| error::file_read_error(file_path))?;
+        self.update(data);
+        Ok(())
+    }
+
+    pub fn finish(self) -> String {
+        use sha2::Digest;
+        self.0.finalize().iter().map(|x| format!("{:02x}", x)).collect()
+    }
+}
+
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+pub struct SplitCacheTile {
+    pub directory_name: String,
+    pub sprite_id: iff::IffChunkId,
+}
+
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+pub struct SplitCacheFrame {
+    pub name: String,
+    pub key: String,
+    pub tiles: Vec<SplitCacheTile>,
+}
+
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+pub struct SplitCachePalette {
+    pub palette_id: iff::IffChunkId,
+    pub key: String,
+    pub frames: Vec<SplitCacheFrame>,
+}
+
+#[derive(Default, serde::Deserialize, serde::Serialize)]
+pub struct SplitCache {
+    pub palettes: Vec<SplitCachePalette>,
+}
+
+impl SplitCache {
+    // A missing or unreadable cache only means that everything is split again
+    pub fn read(cache_directory: &std::path::Path) -> SplitCache {
+        std::fs::read_to_string(cache_directory.join(SPLIT_CACHE_FILE_NAME))
+            .ok()
+            .and_then(|x| serde_json::from_str(&x).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn write(&self, cache_directory: &std::path::Path) -> anyhow::Result<()> {
+        std::fs::create_dir_all(cache_directory)
+            .with_context(|| format!("Failed to create directory {}", cache_directory.display()))?;
+        let split_cache_file_path = cache_directory.join(SPLIT_CACHE_FILE_NAME);
+        let json_string = serde_json::to_string_pretty(self)
+            .with_context(|| format!("Failed to serialize json file {}", split_cache_file_path.display()))?;
+        std::fs::write(&split_cache_file_path, json_string)
+            .with_context(|| error::file_write_error(&split_cache_file_path))
+    }
+
+    pub fn palette(&self, palette_id: iff::IffChunkId) -> Option<&SplitCachePalette> {
+        self.palettes.iter().find(|x| x.palette_id == palette_id)
+    }
+}
+
+// Encoded SPR2 chunks are stored in files named after the hash of everything they were encoded from
+pub struct SpriteChunkCache {
+    directory: std::path::PathBuf,
+    used_keys: std::sync::Mutex<std::collections::HashSet<String>>,
+}
+
+impl SpriteChunkCache {
+    pub fn new(cache_directory: &std::path::Path) -> SpriteChunkCache {
+        SpriteChunkCache {
+            directory: cache_directory.join(SPRITE_CHUNKS_DIRECTORY_NAME),
+            used_keys: std::sync::Mutex::new(std::collections::HashSet::new()),
+        }
+    }
+
+    pub fn spr2_chunk(
+        &self,
+        sprite: &spr::Sprite,
+        source_directory: &std::path::Path,
+        channel_generation: spr::ChannelGeneration,
+        sprite_images: &sprite_image::SpriteImages,
+    ) -> anyhow::Result<iff::IffChunk> {
+        let key = sprite.content_hash(source_directory, channel_generation, sprite_images)?;
+        let chunk_file_path = self.directory.join(&key).with_extension("spr2");
+        self.used_keys.lock().unwrap().insert(key);
+
+        if chunk_file_path.is_file() {
+            let data = std::fs::read(&chunk_file_path).with_context(|| error::file_read_error(&chunk_file_path))?;
+            let header = iff::IffChunkHeader::new(b"SPR2", data.len(), sprite.chunk_id, &sprite.chunk_label)?;
+            return Ok(iff::IffChunk { header, data });
+        }
+
+        let chunk = sprite.to_chunk(source_directory, channel_generation, sprite_images)?;
+        std::fs::create_dir_all(&self.directory)
+            .with_context(|| format!("Failed to create directory {}", self.directory.display()))?;
+        std::fs::write(&chunk_file_path, &chunk.data).with_context(|| error::file_write_error(&chunk_file_path))?;
+        Ok(chunk)
+    }
+
+    // Chunks of sprites that have changed or were removed are not needed anymore
+    pub fn remove_unused(&self) -> anyhow::Result<()> {
+        if !self.directory.is_dir() {
+            return Ok(());
+        }
+        let used_keys = self.used_keys.lock().unwrap();
+        for entry in std::fs::read_dir(&self.directory).with_context(|| error::file_read_error(&self.directory))? {
+            let path = entry.with_context(|| error::file_read_error(&self.directory))?.path();
+            let is_used = path.file_stem().is_some_and(|x| used_keys.contains(x.to_string_lossy().as_ref()));
+            if !is_used {
+                std::fs::remove_file(&path).with_context(|| format!("Failed to remove {}", path.display()))?;
+            }
+        }
+        Ok(())
+    }
+}
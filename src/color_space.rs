@@ -0,0 +1,72 @@
+#[derive(Copy, Clone, Debug, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ColorSpace {
+    #[default]
+    Rgb,
+    Oklab,
+    Cielab,
+}
+
+impl ColorSpace {
+    pub fn coordinates(self, color: [u8; 3]) -> [f64; 3] {
+        match self {
+            ColorSpace::Rgb => color.map(f64::from),
+            ColorSpace::Oklab => srgb_to_oklab(color),
+            ColorSpace::Cielab => srgb_to_cielab(color),
+        }
+    }
+
+    // Colors closer than this look the same, which is about one just noticeable difference in perceptual color spaces
+    pub fn merge_distance(self) -> f64 {
+        match self {
+            ColorSpace::Rgb => 0.0,
+            ColorSpace::Oklab => 0.01,
+            ColorSpace::Cielab => 1.0,
+        }
+    }
+}
+
+pub fn distance(a: [f64; 3], b: [f64; 3]) -> f64 {
+    ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt()
+}
+
+fn srgb_to_linear(color: [u8; 3]) -> [f64; 3] {
+    color.map(|x| {
+        let x = f64::from(x) / 255.0;
+        if x <= 0.04045 {
+            x / 12.92
+        } else {
+            ((x + 0.055) / 1.055).powf(2.4)
+        }
+    })
+}
+
+// CIELAB uses a D65 white point, the distance between two colors is their ΔE
+pub fn srgb_to_cielab(color: [u8; 3]) -> [f64; 3] {
+    let [r, g, b] = srgb_to_linear(color);
+    let xyz = [
+        (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047,
+        0.2126 * r + 0.7152 * g + 0.0722 * b,
+        (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883,
+    ];
+    let [x, y, z] = xyz.map(|x| {
+        if x > 216.0 / 24389.0 {
+            x.cbrt()
+        } else {
+            (24389.0 / 27.0 * x + 16.0) / 116.0
+        }
+    });
+    [116.0 * y - 16.0, 500.0 * (x - y), 200.0 * (y - z)]
+}
+
+pub fn srgb_to_oklab(color: [u8; 3]) -> [f64; 3] {
+    let [r, g, b] = srgb_to_linear(color);
+    let l = (0.4122214708 * r + 0.5363325363 * g + 0.0514459929 * b).cbrt();
+    let m = (0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b).cbrt();
+    let s = (0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b).cbrt();
+    [
+        0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s,
+        1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s,
+        0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s,
+    ]
+}
@@ -0,0 +1,137 @@
+use crate::error;
+
+use anyhow::Context;
+
+fn default_saturation() -> f32 {
+    1.0
+}
+
+fn default_tint_amount() -> f32 {
+    1.0
+}
+
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+pub struct Tint {
+    pub color: [u8; 3],
+    #[serde(default = "default_tint_amount")]
+    pub amount: f32,
+}
+
+// Hue shifts are in degrees, saturation is scaled and lightness is added. A mask limits the transform to the pixels
+// it covers, from a grayscale render pass named after it in the full sprites.
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+pub struct ColorTransform {
+    #[serde(default)]
+    pub mask: Option<String>,
+    #[serde(default)]
+    pub hue_shift: f32,
+    #[serde(default = "default_saturation")]
+    pub saturation: f32,
+    #[serde(default)]
+    pub lightness: f32,
+    #[serde(default)]
+    pub tint: Option<Tint>,
+}
+
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+pub struct ColorVariant {
+    pub name: String,
+    pub transforms: Vec<ColorTransform>,
+}
+
+fn rgb_to_hsl([r, g, b]: [f32; 3]) -> [f32; 3] {
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let lightness = (max + min) / 2.0;
+    let chroma = max - min;
+    if chroma <= 0.0 {
+        return [0.0, 0.0, lightness];
+    }
+
+    let saturation = chroma / (1.0 - (2.0 * lightness - 1.0).abs());
+    let hue = if max == r {
+        ((g - b) / chroma).rem_euclid(6.0)
+    } else if max == g {
+        (b - r) / chroma + 2.0
+    } else {
+        (r - g) / chroma + 4.0
+    };
+    [hue * 60.0, saturation, lightness]
+}
+
+fn hsl_to_rgb([hue, saturation, lightness]: [f32; 3]) -> [f32; 3] {
+    let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+    let hue = hue.rem_euclid(360.0) / 60.0;
+    let x = chroma * (1.0 - (hue.rem_euclid(2.0) - 1.0).abs());
+    let [r, g, b] = match hue as u32 {
+        0 => [chroma, x, 0.0],
+        1 => [x, chroma, 0.0],
+        2 => [0.0, chroma, x],
+        3 => [0.0, x, chroma],
+        4 => [x, 0.0, chroma],
+        _ => [chroma, 0.0, x],
+    };
+    let m = lightness - chroma / 2.0;
+    [r + m, g + m, b + m]
+}
+
+impl ColorTransform {
+    fn transform_color(&self, color: [f32; 3]) -> [f32; 3] {
+        let [hue, saturation, lightness] = rgb_to_hsl(color);
+        let lightness = (lightness + self.lightness).clamp(0.0, 1.0);
+        let color = hsl_to_rgb([
+            hue + self.hue_shift,
+            (saturation * self.saturation).clamp(0.0, 1.0),
+            lightness,
+        ]);
+
+        // Tinting gives pixels the hue and saturation of the tint color, keeping their lightness
+        match &self.tint {
+            Some(tint) => {
+                let [tint_hue, tint_saturation, _] = rgb_to_hsl(tint.color.map(|x| f32::from(x) / 255.0));
+                let tinted_color = hsl_to_rgb([tint_hue, tint_saturation, lightness]);
+                let amount = tint.amount.clamp(0.0, 1.0);
+                std::array::from_fn(|i| color[i] + (tinted_color[i] - color[i]) * amount)
+            }
+            None => color,
+        }
+    }
+}
+
+// Transforms are applied one after another, each blended by its mask
+pub fn apply(
+    color_transforms: &[ColorTransform],
+    color_sprite: &mut image::RgbImage,
+    full_sprite_frame_directory: &std::path::Path,
+    render_name: &str,
+) -> anyhow::Result<()> {
+    for color_transform in color_transforms {
+        let mask = match &color_transform.mask {
+            Some(mask) => {
+                let mask_file_path = full_sprite_frame_directory.join(format!("{}_{}.png", render_name, mask));
+                let mask =
+                    image::open(&mask_file_path).with_context(|| error::file_read_error(&mask_file_path))?.to_luma8();
+                anyhow::ensure!(
+                    mask.dimensions() == color_sprite.dimensions(),
+                    "Mask {} is not the same size as its color sprite",
+                    mask_file_path.display()
+                );
+                Some(mask)
+            }
+            None => None,
+        };
+
+        for (x, y, pixel) in color_sprite.enumerate_pixels_mut() {
+            let weight = mask.as_ref().map_or(1.0, |mask| f32::from(mask.get_pixel(x, y)[0]) / 255.0);
+            if weight <= 0.0 {
+                continue;
+            }
+            let color = pixel.0.map(|x| f32::from(x) / 255.0);
+            let transformed_color = color_transform.transform_color(color);
+            pixel.0 = std::array::from_fn(|i| {
+                ((color[i] + (transformed_color[i] - color[i]) * weight) * 255.0).round().clamp(0.0, 255.0) as u8
+            });
+        }
+    }
+    Ok(())
+}
@@ -1,6 +1,11 @@
+use crate::build_cache;
 use crate::iff;
 use crate::iff_description;
+use crate::project;
+use crate::splitter;
+use crate::sprite_image;
 use crate::the_sims;
+use crate::xml_updater;
 
 use anyhow::Context;
 
@@ -64,12 +69,10 @@ fn get_formatted_iff_file_path_and_rename_unhashed_iff_file(
     Ok(iff_file_path)
 }
 
-pub fn compile(xml_file_path: &std::path::Path) -> anyhow::Result<()> {
-    let iff_description = iff_description::IffDescription::open(xml_file_path)
+pub fn compile(xml_file_path: &std::path::Path, just_change_colors: bool) -> anyhow::Result<()> {
+    let mut iff_description = iff_description::IffDescription::open(xml_file_path)
         .with_context(|| format!("Failed to open xml file {}", xml_file_path.display()))?;
 
-    let mut iff_description = iff_description.validate()?;
-
     let source_directory = std::path::PathBuf::from(&xml_file_path);
     let source_directory = source_directory.parent().with_context(|| {
         format!(
@@ -77,7 +80,22 @@ pub fn compile(xml_file_path: &std::path::Path) -> anyhow::Result<()> {
             source_directory.display()
         )
     })?;
-    iff_description.update_sprite_positions(source_directory)?;
+    let object_name = xml_file_path.file_stem().unwrap_or_default().to_string_lossy();
+    xml_updater::replace_manifest_sprites(
+        &mut iff_description,
+        xml_file_path,
+        source_directory,
+        &object_name,
+        None,
+    )?;
+
+    let mut iff_description = iff_description.validate()?;
+    // Sprites are kept from the iff file when only changing colors, so their bmp files are not read
+    let just_change_colors = just_change_colors || iff_description.just_changes_colors();
+    let mut sprite_images = sprite_image::SpriteImages::default();
+    if iff_description.exports_sprites() && !just_change_colors {
+        iff_description.update_sprite_positions(source_directory, &sprite_images)?;
+    }
 
     let the_sims_install_path = the_sims::install_path()?;
     let input_iff_file_path =
@@ -85,11 +103,20 @@ pub fn compile(xml_file_path: &std::path::Path) -> anyhow::Result<()> {
 
     iff::rebuild_iff_file(
         source_directory,
+        &object_name,
         &iff_description,
+        &mut sprite_images,
+        &splitter::sprite_quantization(source_directory, &object_name)?,
         &input_iff_file_path,
         &input_iff_file_path,
+        just_change_colors,
+        &build_cache::SpriteChunkCache::new(&build_cache::cache_directory(source_directory, &object_name)),
     )?;
 
+    // Project manifests are not written back to, sprite positions are updated again on every compile
+    if project::is_project_manifest(xml_file_path) {
+        return Ok(());
+    }
     iff_description
         .save(xml_file_path)
         .with_context(|| format!("Failed to save xml file {}", xml_file_path.display()))
@@ -101,20 +128,70 @@ pub fn compile_advanced(
     creator_name: &str,
     object_name: &str,
     variant_names: Option<(&str, &str)>,
+    just_change_colors: bool,
+) -> anyhow::Result<()> {
+    let (variant_original, variant_new) = variant_names.unzip();
+    compile_variant(
+        source_directory,
+        format_string,
+        creator_name,
+        object_name,
+        variant_original,
+        variant_new,
+        just_change_colors,
+    )?;
+
+    // Color variants are compiled from the object's iff file together with the object itself
+    if variant_names.is_none() {
+        for color_variant in splitter::color_variant_names(source_directory, object_name)? {
+            println!("Compiling color variant {}", color_variant);
+            compile_variant(
+                source_directory,
+                format_string,
+                creator_name,
+                object_name,
+                None,
+                Some(&color_variant),
+                just_change_colors,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn compile_variant(
+    source_directory: &std::path::Path,
+    format_string: &str,
+    creator_name: &str,
+    object_name: &str,
+    variant_original: Option<&str>,
+    variant_new: Option<&str>,
+    just_change_colors: bool,
 ) -> anyhow::Result<()> {
-    let xml_file_path = source_directory.join(object_name).with_extension("xml");
+    let xml_file_path = project::description_file_path(source_directory, object_name);
 
-    let iff_description = iff_description::IffDescription::open(&xml_file_path)
+    let mut iff_description = iff_description::IffDescription::open(&xml_file_path)
         .with_context(|| format!("Failed to open xml file {}", xml_file_path.display()))?;
+    xml_updater::replace_manifest_sprites(
+        &mut iff_description,
+        &xml_file_path,
+        source_directory,
+        object_name,
+        variant_original,
+    )?;
 
     let mut iff_description = iff_description.validate()?;
 
-    if let Some((variant_original, variant_new)) = variant_names {
-        iff_description.update_sprite_variants(variant_original, variant_new)?;
+    if let Some(variant_new) = variant_new {
+        iff_description.update_sprite_variants(object_name, variant_original, variant_new)?;
+    }
+    // Sprites are kept from the iff file when only changing colors, so their bmp files are not read
+    let just_change_colors = just_change_colors || iff_description.just_changes_colors();
+    let mut sprite_images = sprite_image::SpriteImages::default();
+    if iff_description.exports_sprites() && !just_change_colors {
+        iff_description.update_sprite_positions(source_directory, &sprite_images)?;
     }
-    iff_description.update_sprite_positions(source_directory)?;
 
-    let (variant_original, variant_new) = variant_names.unzip();
     let the_sims_downloads_path = the_sims::install_path()?.join("downloads");
     let input_iff_file_path = get_formatted_iff_file_path_and_rename_unhashed_iff_file(
         &the_sims_downloads_path,
@@ -131,17 +208,118 @@ pub fn compile_advanced(
         variant_new,
     )?;
 
+    // Each variant has its own cache, as unused chunks are removed from the cache after every compile, and its own
+    // palette files
+    let variant_object_name = match variant_new {
+        Some(variant_new) => format!("{} - {}", object_name, variant_new),
+        None => object_name.to_owned(),
+    };
     iff::rebuild_iff_file(
         source_directory,
+        &variant_object_name,
         &iff_description,
+        &mut sprite_images,
+        &splitter::sprite_quantization(source_directory, object_name)?,
         &input_iff_file_path,
         &output_iff_file_path,
+        just_change_colors,
+        &build_cache::SpriteChunkCache::new(&build_cache::cache_directory(source_directory, &variant_object_name)),
     )?;
 
-    if variant_original == variant_new {
+    if variant_original == variant_new && !project::is_project_manifest(&xml_file_path) {
         iff_description
             .save(&xml_file_path)
             .with_context(|| format!("Failed to save xml file {}", xml_file_path.display()))?;
     }
     Ok(())
 }
+
+pub fn build(source_directory: &std::path::Path, object_name: &str, write_sprites: bool) -> anyhow::Result<()> {
+    let mut split_sprites = splitter::split_sprites(source_directory, object_name, None, false, None, write_sprites)?;
+    if write_sprites {
+        split_sprites.write()?;
+    }
+
+    let xml_file_path = project::description_file_path(source_directory, object_name);
+
+    let mut iff_description = iff_description::IffDescription::open(&xml_file_path)
+        .with_context(|| format!("Failed to open xml file {}", xml_file_path.display()))?;
+
+    let new_sprites = split_sprites
+        .tiles
+        .iter()
+        .map(|x| {
+            xml_updater::build_sprite_description(
+                source_directory,
+                &x.directory,
+                x.sprite_id,
+                &split_sprites.sprite_images,
+            )
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    xml_updater::replace_sprites(&mut iff_description, new_sprites);
+
+    let mut iff_description = iff_description.validate()?;
+    if iff_description.exports_sprites() {
+        iff_description.update_sprite_positions(source_directory, &split_sprites.sprite_images)?;
+    }
+
+    let input_iff_file_path =
+        the_sims::install_path()?.join(&iff_description.iff_file_path_relative).with_extension("iff");
+
+    iff::rebuild_iff_file(
+        source_directory,
+        object_name,
+        &iff_description,
+        &mut split_sprites.sprite_images,
+        &splitter::sprite_quantization(source_directory, object_name)?,
+        &input_iff_file_path,
+        &input_iff_file_path,
+        false,
+        &build_cache::SpriteChunkCache::new(&build_cache::cache_directory(source_directory, object_name)),
+    )?;
+
+    // The xml can only refer to the split sprites once they have been written
+    if !write_sprites || project::is_project_manifest(&xml_file_path) {
+        return Ok(());
+    }
+    iff_description
+        .save(&xml_file_path)
+        .with_context(|| format!("Failed to save xml file {}", xml_file_path.display()))
+}
+
+pub fn check(xml_file_path: &std::path::Path, json: bool) -> anyhow::Result<()> {
+    let mut iff_description = iff_description::IffDescription::read(xml_file_path)
+        .with_context(|| format!("Failed to open xml file {}", xml_file_path.display()))?;
+    let source_directory = xml_file_path.parent().with_context(|| {
+        format!(
+            "Failed to get source directory from xml file path {}",
+            xml_file_path.display()
+        )
+    })?;
+    let object_name = xml_file_path.file_stem().unwrap_or_default().to_string_lossy();
+    xml_updater::replace_manifest_sprites(
+        &mut iff_description,
+        xml_file_path,
+        source_directory,
+        &object_name,
+        None,
+    )?;
+
+    let diagnostics = iff_description.diagnose();
+    if json {
+        println!("{}", diagnostics.to_json()?);
+    } else {
+        print!("{}", diagnostics.to_text());
+    }
+
+    let error_count = diagnostics.error_count();
+    anyhow::ensure!(
+        error_count == 0,
+        "found {} error{} in {}",
+        error_count,
+        if error_count == 1 { "" } else { "s" },
+        xml_file_path.display()
+    );
+    Ok(())
+}
@@ -0,0 +1,194 @@
+use crate::xml_document;
+
+#[derive(Clone, Default)]
+pub struct ElementPath(String);
+
+impl ElementPath {
+    pub fn root() -> ElementPath {
+        ElementPath(String::new())
+    }
+
+    pub fn child(&self, name: &str, index: usize) -> ElementPath {
+        if self.0.is_empty() {
+            ElementPath(format!("{name}[{index}]"))
+        } else {
+            ElementPath(format!("{}/{name}[{index}]", self.0))
+        }
+    }
+}
+
+impl std::fmt::Display for ElementPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Copy, Clone, serde::Serialize)]
+pub struct TextPosition {
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Default)]
+pub struct XmlLocations {
+    file_path: Option<std::path::PathBuf>,
+    elements: std::collections::HashMap<String, TextPosition>,
+}
+
+impl XmlLocations {
+    pub fn new(file_path: &std::path::Path) -> XmlLocations {
+        XmlLocations {
+            file_path: Some(file_path.to_owned()),
+            elements: std::collections::HashMap::new(),
+        }
+    }
+
+    pub fn from_document(file_path: &std::path::Path, document: &xml_document::XmlDocument) -> XmlLocations {
+        let text = document.text();
+        let line_starts: Vec<_> = std::iter::once(0).chain(text.match_indices('\n').map(|(i, _)| i + 1)).collect();
+        let text_position = |offset: usize| {
+            let line = line_starts.partition_point(|x| *x <= offset);
+            TextPosition {
+                line,
+                column: text[line_starts[line - 1]..offset].chars().count() + 1,
+            }
+        };
+
+        XmlLocations {
+            file_path: Some(file_path.to_owned()),
+            elements: document
+                .element_offsets()
+                .into_iter()
+                .map(|(path, offset)| (path.0, text_position(offset)))
+                .collect(),
+        }
+    }
+
+    fn position(&self, path: &ElementPath) -> Option<TextPosition> {
+        self.elements.get(&path.0).copied()
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let string = match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{}", string)
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: &'static str,
+    pub message: String,
+    pub path: String,
+    #[serde(flatten)]
+    pub position: Option<TextPosition>,
+}
+
+pub struct Diagnostics<'a> {
+    locations: &'a XmlLocations,
+    diagnostics: Vec<Diagnostic>,
+    disabled_warnings: std::collections::HashSet<String>,
+}
+
+impl<'a> Diagnostics<'a> {
+    pub fn new(locations: &'a XmlLocations) -> Diagnostics<'a> {
+        Diagnostics {
+            locations,
+            diagnostics: Vec::new(),
+            disabled_warnings: std::collections::HashSet::new(),
+        }
+    }
+
+    pub fn disable_warnings(&mut self, codes: &[String]) {
+        self.disabled_warnings.extend(codes.iter().cloned());
+    }
+
+    pub fn error(&mut self, code: &'static str, path: &ElementPath, message: String) {
+        self.diagnostics.push(Diagnostic {
+            severity: Severity::Error,
+            code,
+            message,
+            path: path.to_string(),
+            position: self.locations.position(path),
+        });
+    }
+
+    pub fn warning(&mut self, code: &'static str, path: &ElementPath, message: String) {
+        if self.disabled_warnings.contains(code) {
+            return;
+        }
+        self.diagnostics.push(Diagnostic {
+            severity: Severity::Warning,
+            code,
+            message,
+            path: path.to_string(),
+            position: self.locations.position(path),
+        });
+    }
+
+    pub fn append(&mut self, mut other: Diagnostics) {
+        self.diagnostics.append(&mut other.diagnostics);
+    }
+
+    pub fn error_count(&self) -> usize {
+        self.diagnostics.iter().filter(|x| x.severity == Severity::Error).count()
+    }
+
+    pub fn warning_count(&self) -> usize {
+        self.diagnostics.iter().filter(|x| x.severity == Severity::Warning).count()
+    }
+
+    pub fn to_text(&self) -> String {
+        let file_path = self.locations.file_path.as_ref().map(|x| x.display().to_string()).unwrap_or_default();
+
+        let mut text = String::new();
+        for diagnostic in &self.diagnostics {
+            let location = match diagnostic.position {
+                Some(position) => format!("{}:{}:{}", file_path, position.line, position.column),
+                None => format!("{} {}", file_path, diagnostic.path),
+            };
+            text += &format!(
+                "{}: {}[{}]: {}\n",
+                location, diagnostic.severity, diagnostic.code, diagnostic.message
+            );
+        }
+        text
+    }
+
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        #[derive(serde::Serialize)]
+        struct DiagnosticsReport<'a> {
+            file: Option<&'a std::path::Path>,
+            diagnostics: &'a [Diagnostic],
+        }
+
+        Ok(serde_json::to_string_pretty(&DiagnosticsReport {
+            file: self.locations.file_path.as_deref(),
+            diagnostics: &self.diagnostics,
+        })?)
+    }
+
+    pub fn into_result(self) -> anyhow::Result<()> {
+        let error_count = self.error_count();
+        anyhow::ensure!(
+            error_count == 0,
+            "found {} error{}\n{}",
+            error_count,
+            if error_count == 1 { "" } else { "s" },
+            self.to_text().trim_end()
+        );
+        Ok(())
+    }
+}
@@ -1,9 +1,16 @@
 use crate::iff_description;
+use crate::project;
 use crate::spr;
 
 use anyhow::Context;
 
 pub fn add_rotations(xml_file_path: &std::path::Path) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        !project::is_project_manifest(xml_file_path),
+        "Adding rotations to project manifest {} is not supported, convert it to xml first",
+        xml_file_path.display()
+    );
+
     let mut iff_description = iff_description::IffDescription::open(xml_file_path)
         .with_context(|| format!("Failed to open xml file {}", xml_file_path.display()))?;
 
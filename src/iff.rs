@@ -1,7 +1,10 @@
+use crate::build_cache;
 use crate::error;
 use crate::iff_description;
 use crate::palt;
+use crate::quantizer;
 use crate::spr;
+use crate::sprite_image;
 
 use anyhow::Context;
 
@@ -10,6 +13,8 @@ pub const IFF_HEADER_SIZE: usize = 64;
 pub const IFF_CHUNK_HEADER_SIZE: usize = 76;
 pub const IFF_CHUNK_LABEL_SIZE: usize = 64;
 
+const OBJD_GUID_ADDRESS_OFFSET: usize = 28;
+
 #[derive(
     Copy, Clone, Debug, Eq, PartialEq, Hash, binrw::BinRead, binrw::BinWrite, serde::Deserialize, serde::Serialize,
 )]
@@ -35,7 +40,7 @@ impl std::ops::Add<i16> for IffChunkId {
 
 #[derive(Clone, Debug, binrw::BinRead, binrw::BinWrite)]
 #[brw(big)]
-#[brw(assert(label.iter().any(|x|*x == b'\0')))]
+#[brw(assert(label.contains(&b'\0')))]
 pub struct IffChunkHeader {
     chunk_type: [u8; 4],
     size: u32,
@@ -68,6 +73,10 @@ impl IffChunkHeader {
             label,
         })
     }
+
+    pub fn id(&self) -> IffChunkId {
+        self.id
+    }
 }
 
 #[derive(Clone, binrw::BinRead, binrw::BinWrite)]
@@ -106,13 +115,17 @@ fn read_iff_file(iff_file_path: &std::path::Path) -> anyhow::Result<Iff> {
     Ok(iff)
 }
 
+pub fn read_chunks(iff_file_path: &std::path::Path, chunk_type: &[u8; 4]) -> anyhow::Result<Vec<IffChunk>> {
+    let iff = read_iff_file(iff_file_path)?;
+    Ok(iff.chunks.into_iter().filter(|x| &x.header.chunk_type == chunk_type).collect())
+}
+
 fn map_guids(chunks: &[IffChunk]) -> std::collections::HashMap<IffChunkId, i32> {
     let mut guids = std::collections::HashMap::new();
     for chunk in chunks {
         if &chunk.header.chunk_type == b"OBJD" {
-            const GUID_ADDRESS_OFFSET: usize = 28;
             let guid = i32::from_le_bytes(
-                chunk.data.get(GUID_ADDRESS_OFFSET..GUID_ADDRESS_OFFSET + 4).unwrap().try_into().unwrap(),
+                chunk.data.get(OBJD_GUID_ADDRESS_OFFSET..OBJD_GUID_ADDRESS_OFFSET + 4).unwrap().try_into().unwrap(),
             );
             guids.entry(chunk.header.id).or_insert(guid);
         }
@@ -120,13 +133,28 @@ fn map_guids(chunks: &[IffChunk]) -> std::collections::HashMap<IffChunkId, i32>
     guids
 }
 
+fn replace_guids_in_objds(chunks: &mut [IffChunk], output_guids: &std::collections::HashMap<IffChunkId, i32>) {
+    for chunk in chunks {
+        if &chunk.header.chunk_type == b"OBJD" {
+            if let Some(output_guid) = output_guids.get(&chunk.header.id) {
+                chunk
+                    .data
+                    .get_mut(OBJD_GUID_ADDRESS_OFFSET..OBJD_GUID_ADDRESS_OFFSET + 4)
+                    .unwrap()
+                    .copy_from_slice(&output_guid.to_le_bytes());
+            }
+        }
+    }
+}
+
 fn create_rsmp_chunk(chunks: &[IffChunk]) -> IffChunk {
-    let mut chunk_descriptions = std::collections::HashMap::new();
+    // Chunk types are listed in the order they first appear so that the same chunks always give the same rsmp
+    let mut chunk_descriptions: Vec<([u8; 4], Vec<_>)> = Vec::new();
     chunks.iter().fold(IFF_HEADER_SIZE as u32, |address, chunk| {
-        chunk_descriptions
-            .entry(chunk.header.chunk_type)
-            .or_insert_with(Vec::new)
-            .push((chunk.header.clone(), address));
+        match chunk_descriptions.iter_mut().find(|x| x.0 == chunk.header.chunk_type) {
+            Some((_, chunks)) => chunks.push((chunk.header.clone(), address)),
+            None => chunk_descriptions.push((chunk.header.chunk_type, vec![(chunk.header.clone(), address)])),
+        }
         address + chunk.header.size
     });
 
@@ -202,11 +230,17 @@ fn replace_guids_in_bhavs(
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn rebuild_iff_file(
     source_directory: &std::path::Path,
+    object_name: &str,
     iff_description: &iff_description::IffDescription,
+    sprite_images: &mut sprite_image::SpriteImages,
+    sprite_quantization: &quantizer::SpriteQuantization,
     input_iff_file_path: &std::path::Path,
     output_iff_file_path: &std::path::Path,
+    just_change_colors: bool,
+    sprite_chunk_cache: &build_cache::SpriteChunkCache,
 ) -> anyhow::Result<()> {
     let mut iff = read_iff_file(input_iff_file_path)?;
 
@@ -237,30 +271,52 @@ pub fn rebuild_iff_file(
         );
     }
 
-    iff.chunks.retain(|x| {
-        !matches!(
-            &x.header.chunk_type,
-            b"DGRP" | b"OBJD" | b"PALT" | b"SLOT" | b"SPR#" | b"SPR2" | b"rsmp"
-        )
-    });
+    // Only the chunk types enabled by the export flags are replaced, all other chunks are kept from the input iff
+    let replaced_chunk_types = {
+        let mut replaced_chunk_types: Vec<&[u8; 4]> = vec![b"rsmp"];
+        if iff_description.exports_object_definitions() {
+            replaced_chunk_types.push(b"OBJD");
+        }
+        if iff_description.exports_slots() {
+            replaced_chunk_types.push(b"SLOT");
+        }
+        if iff_description.exports_draw_groups() {
+            replaced_chunk_types.push(b"DGRP");
+        }
+        if just_change_colors {
+            replaced_chunk_types.push(b"PALT");
+        } else if iff_description.exports_sprites() {
+            replaced_chunk_types.extend([b"PALT", b"SPR#", b"SPR2"]);
+        }
+        replaced_chunk_types
+    };
+    iff.chunks.retain(|x| !replaced_chunk_types.contains(&&x.header.chunk_type));
 
-    for object_definition in &iff_description.object_definitions.object_definitions {
-        let replacement_guid = *output_guids.get(&object_definition.chunk_id).with_context(|| {
-            format!(
-                "Failed to find replacement GUID for object {} {}",
-                object_definition.chunk_id.as_i32(),
-                object_definition.chunk_label
-            )
-        })?;
-        iff.chunks.push(object_definition.to_chunk(Some(replacement_guid))?);
+    if iff_description.exports_object_definitions() {
+        for object_definition in &iff_description.object_definitions.object_definitions {
+            let replacement_guid = *output_guids.get(&object_definition.chunk_id).with_context(|| {
+                format!(
+                    "Failed to find replacement GUID for object {} {}",
+                    object_definition.chunk_id.as_i32(),
+                    object_definition.chunk_label
+                )
+            })?;
+            iff.chunks.push(object_definition.to_chunk(Some(replacement_guid))?);
+        }
+    } else {
+        replace_guids_in_objds(&mut iff.chunks, &output_guids);
     }
 
-    for slot in &iff_description.slots.slots {
-        iff.chunks.push(slot.to_chunk()?);
+    if iff_description.exports_slots() {
+        for slot in &iff_description.slots.slots {
+            iff.chunks.push(slot.to_chunk()?);
+        }
     }
 
-    for draw_group in &iff_description.draw_groups.draw_groups {
-        iff.chunks.push(draw_group.to_chunk()?);
+    if iff_description.exports_draw_groups() {
+        for draw_group in &iff_description.draw_groups.draw_groups {
+            iff.chunks.push(draw_group.to_chunk()?);
+        }
     }
 
     let used_sprite_ids = {
@@ -275,14 +331,48 @@ pub fn rebuild_iff_file(
         used_sprite_ids
     };
 
-    let palt_chunks = palt::create_palt_chunks(source_directory, &iff_description.sprites.sprites)?;
-    iff.chunks.extend(palt_chunks);
+    // Truecolor color channels are quantized first, as their palettes are only known afterwards
+    if iff_description.exports_sprites() && !just_change_colors {
+        sprite_images.quantize_truecolor_sprites(
+            source_directory,
+            &iff_description.sprites.sprites,
+            iff_description.channel_generation(),
+            sprite_quantization,
+        )?;
+    }
 
-    for sprite in &iff_description.sprites.sprites {
-        if sprite.sprite_type == spr::SpriteType::Spr2 && !used_sprite_ids.contains(&sprite.chunk_id) {
-            continue;
-        }
-        iff.chunks.push(sprite.to_chunk(source_directory)?);
+    if just_change_colors || iff_description.exports_sprites() {
+        let palt_chunks = palt::create_palt_chunks(
+            source_directory,
+            object_name,
+            &iff_description.sprites.sprites,
+            sprite_images,
+        )?;
+        iff.chunks.extend(palt_chunks);
+    }
+
+    if iff_description.exports_sprites() && !just_change_colors {
+        use rayon::prelude::*;
+        let sprite_images = &*sprite_images;
+        let sprite_chunks = iff_description
+            .sprites
+            .sprites
+            .par_iter()
+            .filter(|x| x.sprite_type != spr::SpriteType::Spr2 || used_sprite_ids.contains(&x.chunk_id))
+            .map(|x| match x.sprite_type {
+                spr::SpriteType::Spr1 => {
+                    x.to_chunk(source_directory, iff_description.channel_generation(), sprite_images)
+                }
+                spr::SpriteType::Spr2 => sprite_chunk_cache.spr2_chunk(
+                    x,
+                    source_directory,
+                    iff_description.channel_generation(),
+                    sprite_images,
+                ),
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        iff.chunks.extend(sprite_chunks);
+        sprite_chunk_cache.remove_unused()?;
     }
 
     iff.chunks.push(create_rsmp_chunk(&iff.chunks));
@@ -1,9 +1,12 @@
 use crate::dgrp;
-use crate::error;
+use crate::diagnostics;
 use crate::objd;
+use crate::project;
 use crate::slot;
 use crate::spr;
 use crate::sprite;
+use crate::sprite_image;
+use crate::xml_document;
 
 use anyhow::Context;
 
@@ -52,14 +55,18 @@ pub struct IffDescription {
     createsubdirectories: i32,
     #[serde(rename = "@thingstodo")]
     thingstodo: i32,
-    #[serde(rename = "objectdefinitions", deserialize_with = "deserialize_object_definitions")]
+    #[serde(rename = "objectdefinitions")]
     pub object_definitions: ObjectDefinitions,
     #[serde(rename = "slots")]
     pub slots: Slots,
-    #[serde(rename = "drawgroups", deserialize_with = "deserialize_draw_groups")]
+    #[serde(rename = "drawgroups")]
     pub draw_groups: DrawGroups,
-    #[serde(rename = "sprites", deserialize_with = "spr::deserialize_sprites")]
+    #[serde(rename = "sprites")]
     pub sprites: Sprites,
+    #[serde(skip)]
+    pub locations: diagnostics::XmlLocations,
+    #[serde(skip)]
+    document: Option<xml_document::XmlDocument>,
 }
 
 #[derive(serde::Deserialize, serde::Serialize)]
@@ -92,11 +99,38 @@ pub struct Sprites {
 
 impl IffDescription {
     pub fn open(xml_file_path: &std::path::Path) -> anyhow::Result<IffDescription> {
-        let iff_description = std::fs::read_to_string(xml_file_path)?;
-        Ok(quick_xml::de::from_str::<IffDescription>(&iff_description)?)
+        let iff_description = IffDescription::read(xml_file_path)?;
+        iff_description.check_structure().into_result()?;
+        Ok(iff_description)
+    }
+
+    pub fn read(xml_file_path: &std::path::Path) -> anyhow::Result<IffDescription> {
+        if project::is_project_manifest(xml_file_path) {
+            let xml = project::read_xml(xml_file_path)?;
+            let mut iff_description = quick_xml::de::from_str::<IffDescription>(&xml)?;
+            iff_description.locations = diagnostics::XmlLocations::new(xml_file_path);
+            return Ok(iff_description);
+        }
+
+        let xml = std::fs::read_to_string(xml_file_path)?;
+        let mut iff_description = quick_xml::de::from_str::<IffDescription>(&xml)?;
+        let document = xml_document::XmlDocument::parse(&xml)?;
+        iff_description.locations = diagnostics::XmlLocations::from_document(xml_file_path, &document);
+        iff_description.document = Some(document);
+        Ok(iff_description)
     }
 
     pub fn save(&self, xml_file_path: &std::path::Path) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            !project::is_project_manifest(xml_file_path),
+            "Saving to project manifest {} is not supported, convert it to xml first",
+            xml_file_path.display()
+        );
+
+        Ok(std::fs::write(xml_file_path, self.to_xml()?)?)
+    }
+
+    pub fn to_xml(&self) -> anyhow::Result<String> {
         let xml_header = include_str!("../res/header.xml");
 
         let mut buffer = xml_header.to_owned();
@@ -105,10 +139,181 @@ impl IffDescription {
         use serde::Serialize;
         self.serialize(serializer)?;
 
-        Ok(std::fs::write(xml_file_path, &buffer)?)
+        // Apply the changes to the original document so comments, formatting and attribute order are kept
+        if let Some(document) = &self.document {
+            buffer = document.merge(&buffer)?;
+        }
+
+        Ok(buffer)
+    }
+
+    pub fn exports_object_definitions(&self) -> bool {
+        self.exportobjectdefinitions != 0
+    }
+
+    pub fn exports_slots(&self) -> bool {
+        self.exportslots != 0
+    }
+
+    pub fn exports_draw_groups(&self) -> bool {
+        self.exportdrawgroups != 0
+    }
+
+    pub fn exports_sprites(&self) -> bool {
+        self.exportsprites != 0
+    }
+
+    pub fn just_changes_colors(&self) -> bool {
+        self.justchangecolors != 0
+    }
+
+    pub fn channel_generation(&self) -> spr::ChannelGeneration {
+        spr::ChannelGeneration {
+            depth: if self.generatezfar != 0 {
+                Some(spr::DepthGeneration::Far)
+            } else if self.generatez != 0 {
+                Some(spr::DepthGeneration::Flat)
+            } else {
+                None
+            },
+            alpha: if self.generateasoft != 0 {
+                Some(spr::AlphaGeneration::Soft)
+            } else if self.generatea != 0 {
+                Some(spr::AlphaGeneration::Hard)
+            } else {
+                None
+            },
+        }
     }
 
     pub fn validate(self) -> anyhow::Result<IffDescription> {
+        self.check_references().into_result()?;
+        Ok(self)
+    }
+
+    pub fn diagnose(&self) -> diagnostics::Diagnostics<'_> {
+        let mut diagnostics = self.check_structure();
+        diagnostics.append(self.check_references());
+        diagnostics
+    }
+
+    fn check_structure(&self) -> diagnostics::Diagnostics<'_> {
+        let mut diagnostics = diagnostics::Diagnostics::new(&self.locations);
+        self.check_export_flags(&mut diagnostics);
+        self.check_object_definitions(&mut diagnostics);
+        self.check_draw_groups(&mut diagnostics);
+        spr::check_sprites(&self.sprites.sprites, &mut diagnostics);
+        diagnostics
+    }
+
+    // Sprites are always compiled with every zoom level and channel, so these flags can't be turned off
+    fn check_export_flags(&self, diagnostics: &mut diagnostics::Diagnostics) {
+        for (name, value) in [
+            ("exportallzooms", self.exportallzooms),
+            ("exportp", self.exportp),
+            ("exportz", self.exportz),
+            ("exporta", self.exporta),
+        ] {
+            if value == 0 {
+                diagnostics.error(
+                    "unsupported-export-flag",
+                    &diagnostics::ElementPath::root(),
+                    format!("{} is not supported and has to be 1", name),
+                );
+            }
+        }
+    }
+
+    fn check_object_definitions(&self, diagnostics: &mut diagnostics::Diagnostics) {
+        let objds = &self.object_definitions.object_definitions;
+        let object_definitions_path = diagnostics::ElementPath::root().child("objectdefinitions", 0);
+
+        if objds.is_empty() {
+            diagnostics.error(
+                "no-object-definitions",
+                &object_definitions_path,
+                "no object definitions found".to_owned(),
+            );
+        }
+
+        let mut chunk_ids = std::collections::HashMap::new();
+        let mut guids = std::collections::HashMap::new();
+        for (i, object_definition) in objds.iter().enumerate() {
+            let path = object_definitions_path.child("objectdefinition", i);
+            if let Some(first_object_definition) = chunk_ids.insert(object_definition.chunk_id, object_definition) {
+                diagnostics.error(
+                    "duplicate-object-definition-id",
+                    &path,
+                    format!(
+                        "object definition {} {} has the same chunk ID as object definition {} {}",
+                        object_definition.chunk_id.as_i16(),
+                        object_definition.chunk_label,
+                        first_object_definition.chunk_id.as_i16(),
+                        first_object_definition.chunk_label
+                    ),
+                );
+            }
+            if let Some(first_object_definition) = guids.insert(object_definition.guid, object_definition) {
+                diagnostics.error(
+                    "duplicate-guid",
+                    &path,
+                    format!(
+                        "object definition {} {} has the same GUID as object definition {} {}",
+                        object_definition.chunk_id.as_i16(),
+                        object_definition.chunk_label,
+                        first_object_definition.chunk_id.as_i16(),
+                        first_object_definition.chunk_label
+                    ),
+                );
+            }
+        }
+    }
+
+    fn check_draw_groups(&self, diagnostics: &mut diagnostics::Diagnostics) {
+        let rotations = [
+            sprite::Rotation::SouthEast,
+            sprite::Rotation::NorthEast,
+            sprite::Rotation::NorthWest,
+            sprite::Rotation::SouthWest,
+        ];
+        let zoom_levels = [sprite::ZoomLevel::Zero, sprite::ZoomLevel::One, sprite::ZoomLevel::Two];
+
+        let draw_groups_path = diagnostics::ElementPath::root().child("drawgroups", 0);
+        for (draw_group_index, draw_group) in self.draw_groups.draw_groups.iter().enumerate() {
+            let draw_group_path = draw_groups_path.child("drawgroup", draw_group_index);
+            for (i, draw_group_item_list) in draw_group.draw_group_item_lists.iter().enumerate() {
+                let path = draw_group_path.child("drawgroupitemlist", i);
+                if draw_group_item_list.rotation != rotations[i % 4] {
+                    diagnostics.error(
+                        "incorrect-rotation",
+                        &path,
+                        format!(
+                            "incorrect rotation in draw group {} {} item list {}",
+                            draw_group.chunk_id.as_i16(),
+                            draw_group.chunk_label,
+                            i,
+                        ),
+                    );
+                }
+                if draw_group_item_list.zoom_level != zoom_levels[i / 4] {
+                    diagnostics.error(
+                        "incorrect-zoom-level",
+                        &path,
+                        format!(
+                            "incorrect zoom level in draw group {} {} item list {}",
+                            draw_group.chunk_id.as_i16(),
+                            draw_group.chunk_label,
+                            i,
+                        ),
+                    );
+                }
+            }
+        }
+    }
+
+    fn check_references(&self) -> diagnostics::Diagnostics<'_> {
+        let mut diagnostics = diagnostics::Diagnostics::new(&self.locations);
+
         let object_definitions = &self.object_definitions.object_definitions;
         let slots = &self.slots.slots;
         let draw_groups = &self.draw_groups.draw_groups;
@@ -118,87 +323,123 @@ impl IffDescription {
         let draw_group_ids = draw_groups.iter().map(|x| x.chunk_id).collect::<std::collections::HashSet<_>>();
         let sprite_ids = sprites.iter().map(|x| x.chunk_id).collect::<std::collections::HashSet<_>>();
 
-        for object_definition in object_definitions {
-            if object_definition.slot_chunk_id.as_i16() != 0 {
-                anyhow::ensure!(
-                    slot_ids.contains(&object_definition.slot_chunk_id),
-                    "failed to find slot {} used in object definition {} {}",
-                    object_definition.slot_chunk_id.as_i16(),
-                    object_definition.chunk_id.as_i16(),
-                    object_definition.chunk_label
+        let object_definitions_path = diagnostics::ElementPath::root().child("objectdefinitions", 0);
+        for (object_definition_index, object_definition) in object_definitions.iter().enumerate() {
+            let path = object_definitions_path.child("objectdefinition", object_definition_index);
+
+            if object_definition.slot_chunk_id.as_i16() != 0 && !slot_ids.contains(&object_definition.slot_chunk_id) {
+                diagnostics.error(
+                    "missing-slot",
+                    &path,
+                    format!(
+                        "failed to find slot {} used in object definition {} {}",
+                        object_definition.slot_chunk_id.as_i16(),
+                        object_definition.chunk_id.as_i16(),
+                        object_definition.chunk_label
+                    ),
                 );
             }
 
             if object_definition.subindex != -1 {
                 for i in 0..object_definition.draw_group_count {
                     let draw_group_chunk_id = object_definition.base_draw_group_chunk_id + i;
-                    anyhow::ensure!(
-                        draw_group_ids.contains(&draw_group_chunk_id),
-                        "failed to find draw group {} used in object definition {} {}",
-                        draw_group_chunk_id.as_i16(),
-                        object_definition.chunk_id.as_i16(),
-                        object_definition.chunk_label
-                    );
+                    if !draw_group_ids.contains(&draw_group_chunk_id) {
+                        diagnostics.error(
+                            "missing-draw-group",
+                            &path,
+                            format!(
+                                "failed to find draw group {} used in object definition {} {}",
+                                draw_group_chunk_id.as_i16(),
+                                object_definition.chunk_id.as_i16(),
+                                object_definition.chunk_label
+                            ),
+                        );
+                    }
                 }
                 for i in 0..object_definition.dynamic_sprite_count {
                     let sprite_chunk_id = object_definition.base_dynamic_sprite_chunk_id + i;
-                    anyhow::ensure!(
-                        sprite_ids.contains(&sprite_chunk_id),
-                        "failed to find dynamic sprite {} used in object definition {} {}",
-                        sprite_chunk_id.as_i16(),
-                        object_definition.chunk_id.as_i16(),
-                        object_definition.chunk_label
-                    );
+                    if !sprite_ids.contains(&sprite_chunk_id) {
+                        diagnostics.error(
+                            "missing-dynamic-sprite",
+                            &path,
+                            format!(
+                                "failed to find dynamic sprite {} used in object definition {} {}",
+                                sprite_chunk_id.as_i16(),
+                                object_definition.chunk_id.as_i16(),
+                                object_definition.chunk_label
+                            ),
+                        );
+                    }
                 }
             }
         }
 
-        for draw_group in draw_groups {
+        let draw_groups_path = diagnostics::ElementPath::root().child("drawgroups", 0);
+        for (draw_group_index, draw_group) in draw_groups.iter().enumerate() {
+            let draw_group_path = draw_groups_path.child("drawgroup", draw_group_index);
             for (i, draw_group_item_list) in draw_group.draw_group_item_lists.iter().enumerate() {
-                for draw_group_item in &draw_group_item_list.draw_group_items {
+                let draw_group_item_list_path = draw_group_path.child("drawgroupitemlist", i);
+                for (item_index, draw_group_item) in draw_group_item_list.draw_group_items.iter().enumerate() {
+                    let path = draw_group_item_list_path.child("drawgroupitem", item_index);
                     if let Some(sprite) = sprites.iter().find(|x| x.chunk_id == draw_group_item.sprite_chunk_id) {
-                        anyhow::ensure!(
-                            sprite.sprite_frame_count > draw_group_item.sprite_index.as_i32(),
-                            "failed to find frame {} of sprite {} used in draw group {} {} item list {}",
-                            draw_group_item.sprite_index.as_i32(),
-                            draw_group_item.sprite_chunk_id.as_i16(),
-                            draw_group.chunk_id.as_i16(),
-                            draw_group.chunk_label,
-                            i,
-                        );
+                        if sprite.sprite_frame_count <= draw_group_item.sprite_index.as_i32() {
+                            diagnostics.error(
+                                "missing-frame",
+                                &path,
+                                format!(
+                                    "failed to find frame {} of sprite {} used in draw group {} {} item list {}",
+                                    draw_group_item.sprite_index.as_i32(),
+                                    draw_group_item.sprite_chunk_id.as_i16(),
+                                    draw_group.chunk_id.as_i16(),
+                                    draw_group.chunk_label,
+                                    i,
+                                ),
+                            );
+                        }
                     } else {
-                        anyhow::bail!(
-                            "failed to find sprite {} used in draw group {} {} item list {}",
-                            draw_group_item.sprite_chunk_id.as_i16(),
-                            draw_group.chunk_id.as_i16(),
-                            draw_group.chunk_label,
-                            i,
+                        diagnostics.error(
+                            "missing-sprite",
+                            &path,
+                            format!(
+                                "failed to find sprite {} used in draw group {} {} item list {}",
+                                draw_group_item.sprite_chunk_id.as_i16(),
+                                draw_group.chunk_id.as_i16(),
+                                draw_group.chunk_label,
+                                i,
+                            ),
                         );
                     }
                 }
             }
         }
 
-        Ok(self)
+        diagnostics
     }
 
-    pub fn update_sprite_variants(&mut self, variant_original: &str, variant_new: &str) -> anyhow::Result<()> {
-        let variant_original = " - ".to_owned() + variant_original + " - sprites";
-        let variant_new = " - ".to_owned() + variant_new + " - sprites";
+    // Without an original variant, the sprites of the object itself are replaced, as for its color variants
+    pub fn update_sprite_variants(
+        &mut self,
+        object_name: &str,
+        variant_original: Option<&str>,
+        variant_new: &str,
+    ) -> anyhow::Result<()> {
+        let (variant_original, variant_new) = match variant_original {
+            Some(variant_original) => (
+                " - ".to_owned() + variant_original + " - sprites",
+                " - ".to_owned() + variant_new + " - sprites",
+            ),
+            None => (
+                object_name.to_owned() + " - sprites",
+                object_name.to_owned() + " - " + variant_new + " - sprites",
+            ),
+        };
 
         for sprite in &mut self.sprites.sprites {
             if sprite.sprite_type == spr::SpriteType::Spr1 {
                 continue;
             }
             for frame in &mut sprite.sprite_frames {
-                for channel in [
-                    spr::SpriteChannelType::Color,
-                    spr::SpriteChannelType::Depth,
-                    spr::SpriteChannelType::Alpha,
-                ]
-                .iter()
-                {
-                    let sprite_file_path = frame.sprite_channel_file_path_relative_mut(*channel, sprite.chunk_id)?;
+                for sprite_file_path in frame.sprite_channel_file_paths_relative_mut() {
                     *sprite_file_path = sprite_file_path.replacen(&variant_original, &variant_new, 1);
                 }
             }
@@ -206,7 +447,12 @@ impl IffDescription {
         Ok(())
     }
 
-    pub fn update_sprite_positions(&mut self, source_directory: &std::path::Path) -> anyhow::Result<()> {
+    pub fn update_sprite_positions(
+        &mut self,
+        source_directory: &std::path::Path,
+        sprite_images: &sprite_image::SpriteImages,
+    ) -> anyhow::Result<()> {
+        let channel_generation = self.channel_generation();
         for sprite in &mut self.sprites.sprites {
             if sprite.sprite_type == spr::SpriteType::Spr1 {
                 continue;
@@ -214,24 +460,29 @@ impl IffDescription {
             for frame in &mut sprite.sprite_frames {
                 frame.palette_chunk_id = sprite.palette_chunk_id;
 
-                let alpha_sprite_file_path = source_directory
-                    .join(frame.sprite_channel_file_path_relative(spr::SpriteChannelType::Alpha, sprite.chunk_id)?);
-                let sprite_frame_directory = alpha_sprite_file_path.parent().with_context(|| {
+                let color_sprite_file_path = source_directory
+                    .join(frame.sprite_channel_file_path_relative(spr::SpriteChannelType::Color, sprite.chunk_id)?);
+                let sprite_frame_directory = color_sprite_file_path.parent().with_context(|| {
                     format!(
                         "Failed to get sprite frame directory from sprite file path {}",
-                        alpha_sprite_file_path.display()
+                        color_sprite_file_path.display()
                     )
                 })?;
 
                 let sprite_description_file_path =
                     sprite::sprite_description_file_path(sprite_frame_directory, frame.zoom_level, frame.rotation);
 
-                let sprite_image_description = if sprite_description_file_path.is_file() {
-                    sprite::read_sprite_image_description_file(&sprite_description_file_path)?
+                let sprite_image_description = if let Some(sprite_image_description) =
+                    sprite_images.read_description(&sprite_description_file_path)?
+                {
+                    sprite_image_description
                 } else {
-                    let sprite_image = image::open(&alpha_sprite_file_path)
-                        .with_context(|| error::file_read_error(&alpha_sprite_file_path))?
-                        .to_luma8();
+                    let sprite_image = frame.read_alpha_sprite(
+                        source_directory,
+                        sprite.chunk_id,
+                        channel_generation,
+                        sprite_images,
+                    )?;
                     let sprite_image_description = sprite::calculate_sprite_image_description(
                         &sprite_image,
                         frame.zoom_level,
@@ -274,72 +525,3 @@ impl IffDescription {
         Ok(())
     }
 }
-
-fn deserialize_object_definitions<'de, D>(deserializer: D) -> Result<ObjectDefinitions, D::Error>
-where
-    D: serde::Deserializer<'de>,
-{
-    use serde::Deserialize;
-    let object_definitions = ObjectDefinitions::deserialize(deserializer)?;
-
-    let objds = &object_definitions.object_definitions;
-
-    if objds.is_empty() {
-        return Err(serde::de::Error::custom("no object definitions found"));
-    }
-
-    let chunk_ids: std::collections::HashSet<_> = objds.iter().map(|x| x.chunk_id).collect();
-    if chunk_ids.len() != objds.len() {
-        return Err(serde::de::Error::custom(
-            "object definitions contain entries with the same chunk ID",
-        ));
-    }
-
-    let guids: std::collections::HashSet<_> = objds.iter().map(|x| x.guid).collect();
-    if guids.len() != objds.len() {
-        return Err(serde::de::Error::custom(
-            "object definitions contain entries with the same GUID",
-        ));
-    }
-
-    Ok(object_definitions)
-}
-
-fn deserialize_draw_groups<'de, D>(deserializer: D) -> Result<DrawGroups, D::Error>
-where
-    D: serde::Deserializer<'de>,
-{
-    use serde::Deserialize;
-    let draw_groups = DrawGroups::deserialize(deserializer)?;
-
-    let rotations = [
-        sprite::Rotation::SouthEast,
-        sprite::Rotation::NorthEast,
-        sprite::Rotation::NorthWest,
-        sprite::Rotation::SouthWest,
-    ];
-    let zoom_levels = [sprite::ZoomLevel::Zero, sprite::ZoomLevel::One, sprite::ZoomLevel::Two];
-
-    for draw_group in &draw_groups.draw_groups {
-        for (i, draw_group_item_list) in draw_group.draw_group_item_lists.iter().enumerate() {
-            if draw_group_item_list.rotation != rotations[i % 4] {
-                return Err(serde::de::Error::custom(format!(
-                    "incorrect rotation in draw group {} {} item list {}",
-                    draw_group.chunk_id.as_i16(),
-                    draw_group.chunk_label,
-                    i,
-                )));
-            }
-            if draw_group_item_list.zoom_level != zoom_levels[i / 4] {
-                return Err(serde::de::Error::custom(format!(
-                    "incorrect zoom level in draw group {} {} item list {}",
-                    draw_group.chunk_id.as_i16(),
-                    draw_group.chunk_label,
-                    i,
-                )));
-            }
-        }
-    }
-
-    Ok(draw_groups)
-}
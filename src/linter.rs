@@ -0,0 +1,307 @@
+use crate::diagnostics;
+use crate::error;
+use crate::iff_description;
+use crate::palt;
+use crate::spr;
+use crate::sprite_image;
+use crate::xml_updater;
+
+use anyhow::Context;
+
+#[derive(Default, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LintConfig {
+    #[serde(default)]
+    disabled_warnings: Vec<String>,
+}
+
+const WARNING_CODES: &[&str] = &[
+    "unused-sprite",
+    "palette-mismatch",
+    "empty-frame",
+    "unused-slot",
+    "guid-matches-original-guid",
+    "price-out-of-range",
+];
+
+fn read_lint_config(
+    xml_file_path: &std::path::Path,
+    config_file_path: Option<&std::path::Path>,
+) -> anyhow::Result<LintConfig> {
+    let config_file_path = match config_file_path {
+        Some(config_file_path) => config_file_path.to_owned(),
+        None => {
+            let default_config_file_path = xml_file_path.with_file_name(format!(
+                "{} - lint.json",
+                xml_file_path.file_stem().unwrap_or_default().to_string_lossy()
+            ));
+            if !default_config_file_path.is_file() {
+                return Ok(LintConfig::default());
+            }
+            default_config_file_path
+        }
+    };
+
+    let json_string =
+        std::fs::read_to_string(&config_file_path).with_context(|| error::file_read_error(&config_file_path))?;
+    let lint_config = serde_json::from_str::<LintConfig>(&json_string)
+        .with_context(|| format!("Failed to deserialize json file {}", config_file_path.display()))?;
+
+    for code in &lint_config.disabled_warnings {
+        anyhow::ensure!(
+            WARNING_CODES.contains(&code.as_str()),
+            "unknown warning {} in {}, expected one of {}",
+            code,
+            config_file_path.display(),
+            WARNING_CODES.join(", ")
+        );
+    }
+
+    Ok(lint_config)
+}
+
+fn lint_sprites(
+    source_directory: &std::path::Path,
+    object_name: &str,
+    iff_description: &iff_description::IffDescription,
+    sprite_images: &sprite_image::SpriteImages,
+    diagnostics: &mut diagnostics::Diagnostics,
+) -> anyhow::Result<()> {
+    let used_sprite_ids = {
+        let mut used_sprite_ids = std::collections::HashSet::new();
+        for draw_group in &iff_description.draw_groups.draw_groups {
+            for draw_group_item_list in &draw_group.draw_group_item_lists {
+                for draw_group_item in &draw_group_item_list.draw_group_items {
+                    used_sprite_ids.insert(draw_group_item.sprite_chunk_id);
+                }
+            }
+        }
+        used_sprite_ids
+    };
+
+    let mut palettes = std::collections::HashMap::new();
+
+    let sprites_path = diagnostics::ElementPath::root().child("sprites", 0);
+    for (sprite_index, sprite) in iff_description.sprites.sprites.iter().enumerate() {
+        if sprite.sprite_type == spr::SpriteType::Spr1 {
+            continue;
+        }
+        let path = sprites_path.child("sprite", sprite_index);
+
+        if !used_sprite_ids.contains(&sprite.chunk_id) {
+            diagnostics.warning(
+                "unused-sprite",
+                &path,
+                format!(
+                    "sprite {} {} is not used by any draw group and will not be compiled",
+                    sprite.chunk_id.as_i16(),
+                    sprite.chunk_label
+                ),
+            );
+        }
+
+        let Some(sprite_frame) = sprite.sprite_frames.first() else {
+            continue;
+        };
+        let color_sprite_file_path = source_directory
+            .join(sprite_frame.sprite_channel_file_path_relative(spr::SpriteChannelType::Color, sprite.chunk_id)?);
+        // Truecolor sprites only get their palette when they are compiled
+        if !sprite_images.read_channel_image(&color_sprite_file_path)?.is_indexed() {
+            continue;
+        }
+        let palette = palt::read_palette(
+            source_directory,
+            object_name,
+            sprite.palette_chunk_id,
+            &color_sprite_file_path,
+            sprite_images,
+        )?;
+        match palettes.entry(sprite.palette_chunk_id) {
+            std::collections::hash_map::Entry::Occupied(entry) => {
+                let (first_sprite, first_palette): &(&spr::Sprite, Vec<[u8; 3]>) = entry.get();
+                if *first_palette != palette {
+                    diagnostics.warning(
+                        "palette-mismatch",
+                        &path,
+                        format!(
+                            "sprite {} {} shares palette {} with sprite {} {}, but their palettes differ",
+                            sprite.chunk_id.as_i16(),
+                            sprite.chunk_label,
+                            sprite.palette_chunk_id.as_i16(),
+                            first_sprite.chunk_id.as_i16(),
+                            first_sprite.chunk_label
+                        ),
+                    );
+                }
+            }
+            std::collections::hash_map::Entry::Vacant(entry) => {
+                entry.insert((sprite, palette));
+            }
+        }
+    }
+
+    let draw_groups_path = diagnostics::ElementPath::root().child("drawgroups", 0);
+    for (draw_group_index, draw_group) in iff_description.draw_groups.draw_groups.iter().enumerate() {
+        let draw_group_path = draw_groups_path.child("drawgroup", draw_group_index);
+        for (i, draw_group_item_list) in draw_group.draw_group_item_lists.iter().enumerate() {
+            let draw_group_item_list_path = draw_group_path.child("drawgroupitemlist", i);
+            for (item_index, draw_group_item) in draw_group_item_list.draw_group_items.iter().enumerate() {
+                let frame = iff_description
+                    .sprites
+                    .sprites
+                    .iter()
+                    .find(|x| x.chunk_id == draw_group_item.sprite_chunk_id && x.sprite_type == spr::SpriteType::Spr2)
+                    .and_then(|x| x.sprite_frames.iter().find(|x| x.index == draw_group_item.sprite_index));
+                if let Some(frame) = frame {
+                    if frame.cropped_width == 0 {
+                        diagnostics.warning(
+                            "empty-frame",
+                            &draw_group_item_list_path.child("drawgroupitem", item_index),
+                            format!(
+                                "draw group {} {} item list {} uses frame {} of sprite {}, which is empty",
+                                draw_group.chunk_id.as_i16(),
+                                draw_group.chunk_label,
+                                i,
+                                draw_group_item.sprite_index.as_i32(),
+                                draw_group_item.sprite_chunk_id.as_i16()
+                            ),
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn lint_object_definitions(
+    iff_description: &iff_description::IffDescription,
+    diagnostics: &mut diagnostics::Diagnostics,
+) {
+    let object_definitions = &iff_description.object_definitions.object_definitions;
+
+    let slots_path = diagnostics::ElementPath::root().child("slots", 0);
+    for (slot_index, slot) in iff_description.slots.slots.iter().enumerate() {
+        if !object_definitions.iter().any(|x| x.slot_chunk_id == slot.chunk_id) {
+            diagnostics.warning(
+                "unused-slot",
+                &slots_path.child("slot", slot_index),
+                format!(
+                    "slot {} {} is not used by any object definition",
+                    slot.chunk_id.as_i16(),
+                    slot.chunk_label
+                ),
+            );
+        }
+    }
+
+    let object_definitions_path = diagnostics::ElementPath::root().child("objectdefinitions", 0);
+    for (object_definition_index, object_definition) in object_definitions.iter().enumerate() {
+        let path = object_definitions_path.child("objectdefinition", object_definition_index);
+
+        if object_definition.guid == object_definition.originalguid {
+            diagnostics.warning(
+                "guid-matches-original-guid",
+                &path,
+                format!(
+                    "object definition {} {} has a GUID equal to its original GUID",
+                    object_definition.chunk_id.as_i16(),
+                    object_definition.chunk_label
+                ),
+            );
+        }
+
+        if object_definition.price < 0 {
+            diagnostics.warning(
+                "price-out-of-range",
+                &path,
+                format!(
+                    "object definition {} {} has a negative price of {}",
+                    object_definition.chunk_id.as_i16(),
+                    object_definition.chunk_label,
+                    object_definition.price
+                ),
+            );
+        }
+        if object_definition.saleprice < 0 || object_definition.saleprice > object_definition.price {
+            diagnostics.warning(
+                "price-out-of-range",
+                &path,
+                format!(
+                    "object definition {} {} has a sale price of {}, which is not between 0 and its price of {}",
+                    object_definition.chunk_id.as_i16(),
+                    object_definition.chunk_label,
+                    object_definition.saleprice,
+                    object_definition.price
+                ),
+            );
+        }
+    }
+}
+
+pub fn lint(
+    xml_file_path: &std::path::Path,
+    config_file_path: Option<&std::path::Path>,
+    json: bool,
+) -> anyhow::Result<()> {
+    let lint_config = read_lint_config(xml_file_path, config_file_path)?;
+
+    let mut iff_description = iff_description::IffDescription::read(xml_file_path)
+        .with_context(|| format!("Failed to open xml file {}", xml_file_path.display()))?;
+
+    let source_directory = xml_file_path.parent().with_context(|| {
+        format!(
+            "Failed to get source directory from xml file path {}",
+            xml_file_path.display()
+        )
+    })?;
+    let object_name = xml_file_path.file_stem().unwrap_or_default().to_string_lossy();
+    xml_updater::replace_manifest_sprites(
+        &mut iff_description,
+        xml_file_path,
+        source_directory,
+        &object_name,
+        None,
+    )?;
+
+    let sprite_images = sprite_image::SpriteImages::default();
+    let is_valid = iff_description.diagnose().error_count() == 0;
+    if is_valid {
+        // Frame sizes are only known once the sprite positions have been updated from the sprite files
+        iff_description.update_sprite_positions(source_directory, &sprite_images)?;
+    }
+
+    let mut diagnostics = iff_description.diagnose();
+    if is_valid {
+        diagnostics.disable_warnings(&lint_config.disabled_warnings);
+        lint_sprites(
+            source_directory,
+            &object_name,
+            &iff_description,
+            &sprite_images,
+            &mut diagnostics,
+        )?;
+        lint_object_definitions(&iff_description, &mut diagnostics);
+    }
+
+    if json {
+        println!("{}", diagnostics.to_json()?);
+    } else {
+        print!("{}", diagnostics.to_text());
+        println!(
+            "{} error{}, {} warning{}",
+            diagnostics.error_count(),
+            if diagnostics.error_count() == 1 { "" } else { "s" },
+            diagnostics.warning_count(),
+            if diagnostics.warning_count() == 1 { "" } else { "s" },
+        );
+    }
+
+    anyhow::ensure!(
+        diagnostics.error_count() == 0,
+        "found errors in {}",
+        xml_file_path.display()
+    );
+    Ok(())
+}
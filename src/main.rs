@@ -1,24 +1,42 @@
+mod build_cache;
+mod color_space;
+mod color_variant;
 mod compiler;
 mod dgrp;
+mod diagnostics;
 mod draw_group_rotation_adder;
 mod error;
 mod iff;
 mod iff_description;
+mod linter;
 mod objd;
+mod palette_file;
 mod palt;
+mod previewer;
+mod project;
 mod quantizer;
 mod slot;
+mod split_debug;
+mod split_report;
 mod splitter;
 mod spr;
 mod sprite;
+mod sprite_image;
 mod the_sims;
+mod watcher;
+mod workspace;
+mod xml_document;
 mod xml_updater;
 
+use anyhow::Context;
+
 #[derive(clap::Parser)]
 #[command(version, about, long_about = None)]
 struct Cli {
     #[command(subcommand)]
     command: CliCommands,
+    #[arg(short, long, global = true)]
+    jobs: Option<usize>,
 }
 
 #[derive(clap::Subcommand)]
@@ -28,6 +46,12 @@ enum CliCommands {
         object_name: String,
         #[arg(short, long)]
         variant: Option<String>,
+        #[arg(long)]
+        debug: bool,
+        #[arg(long)]
+        report: bool,
+        #[arg(long, default_value_t = split_report::DEFAULT_MAX_DELTA_E)]
+        max_delta_e: f64,
     },
     UpdateXml {
         source_directory: std::path::PathBuf,
@@ -37,6 +61,8 @@ enum CliCommands {
     },
     Compile {
         xml_file_path: std::path::PathBuf,
+        #[arg(long)]
+        just_change_colors: bool,
     },
     CompileAdvanced {
         source_directory: std::path::PathBuf,
@@ -46,23 +72,83 @@ enum CliCommands {
         #[arg(requires_all=["variant_new"])]
         variant_original: Option<String>,
         variant_new: Option<String>,
+        #[arg(long)]
+        just_change_colors: bool,
+    },
+    Build {
+        source_directory: std::path::PathBuf,
+        object_name: String,
+        #[arg(long)]
+        write_sprites: bool,
     },
     AddRotations {
         xml_file_path: std::path::PathBuf,
     },
+    Check {
+        xml_file_path: std::path::PathBuf,
+        #[arg(long)]
+        json: bool,
+    },
+    Lint {
+        xml_file_path: std::path::PathBuf,
+        #[arg(short, long)]
+        config: Option<std::path::PathBuf>,
+        #[arg(long)]
+        json: bool,
+    },
+    Preview {
+        xml_file_path: std::path::PathBuf,
+        #[arg(short, long)]
+        draw_group: Option<i16>,
+        #[arg(short, long)]
+        output: Option<std::path::PathBuf>,
+    },
+    Convert {
+        input_file_path: std::path::PathBuf,
+        output_file_path: std::path::PathBuf,
+    },
+    Watch {
+        source_directory: std::path::PathBuf,
+        object_name: String,
+    },
+    BuildAll {
+        workspace_file_path: std::path::PathBuf,
+    },
+    ExportPalette {
+        input_file_path: std::path::PathBuf,
+        output_file_path: std::path::PathBuf,
+        #[arg(short, long)]
+        palette_id: Option<i16>,
+    },
 }
 
 fn main() -> anyhow::Result<()> {
     use clap::Parser;
     let cli = Cli::parse();
 
+    if let Some(jobs) = cli.jobs {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(jobs)
+            .build_global()
+            .context("Failed to create thread pool")?;
+    }
+
     match &cli.command {
         CliCommands::Split {
             source_directory,
             object_name,
             variant,
+            debug,
+            report,
+            max_delta_e,
         } => {
-            splitter::split(source_directory, object_name, variant.as_deref())?;
+            splitter::split(
+                source_directory,
+                object_name,
+                variant.as_deref(),
+                *debug,
+                report.then_some(*max_delta_e),
+            )?;
         }
         CliCommands::UpdateXml {
             source_directory,
@@ -71,8 +157,11 @@ fn main() -> anyhow::Result<()> {
         } => {
             xml_updater::update(source_directory, object_name, variant.as_deref())?;
         }
-        CliCommands::Compile { xml_file_path } => {
-            compiler::compile(xml_file_path)?;
+        CliCommands::Compile {
+            xml_file_path,
+            just_change_colors,
+        } => {
+            compiler::compile(xml_file_path, *just_change_colors)?;
         }
         CliCommands::CompileAdvanced {
             source_directory,
@@ -81,6 +170,7 @@ fn main() -> anyhow::Result<()> {
             object_name,
             variant_original,
             variant_new,
+            just_change_colors,
         } => {
             compiler::compile_advanced(
                 source_directory,
@@ -88,11 +178,58 @@ fn main() -> anyhow::Result<()> {
                 creator_name,
                 object_name,
                 variant_original.as_deref().zip(variant_new.as_deref()),
+                *just_change_colors,
             )?;
         }
+        CliCommands::Build {
+            source_directory,
+            object_name,
+            write_sprites,
+        } => {
+            compiler::build(source_directory, object_name, *write_sprites)?;
+        }
         CliCommands::AddRotations { xml_file_path } => {
             draw_group_rotation_adder::add_rotations(xml_file_path)?;
         }
+        CliCommands::Check { xml_file_path, json } => {
+            compiler::check(xml_file_path, *json)?;
+        }
+        CliCommands::Lint {
+            xml_file_path,
+            config,
+            json,
+        } => {
+            linter::lint(xml_file_path, config.as_deref(), *json)?;
+        }
+        CliCommands::Preview {
+            xml_file_path,
+            draw_group,
+            output,
+        } => {
+            previewer::preview(xml_file_path, *draw_group, output.as_deref())?;
+        }
+        CliCommands::Convert {
+            input_file_path,
+            output_file_path,
+        } => {
+            project::convert(input_file_path, output_file_path)?;
+        }
+        CliCommands::Watch {
+            source_directory,
+            object_name,
+        } => {
+            watcher::watch(source_directory, object_name)?;
+        }
+        CliCommands::BuildAll { workspace_file_path } => {
+            workspace::build_all(workspace_file_path)?;
+        }
+        CliCommands::ExportPalette {
+            input_file_path,
+            output_file_path,
+            palette_id,
+        } => {
+            palette_file::export(input_file_path, output_file_path, *palette_id)?;
+        }
     }
     Ok(())
 }
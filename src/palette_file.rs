@@ -0,0 +1,299 @@
+use crate::error;
+use crate::iff;
+use crate::iff_description;
+use crate::palt;
+use crate::splitter;
+use crate::sprite_image;
+use crate::xml_updater;
+
+use anyhow::Context;
+
+pub const PALETTE_FILE_EXTENSIONS: [&str; 4] = ["json", "pal", "gpl", "act"];
+
+const JASC_PALETTE_HEADER: &str = "JASC-PAL";
+const JASC_PALETTE_VERSION: &str = "0100";
+const GIMP_PALETTE_HEADER: &str = "GIMP Palette";
+
+fn extension(file_path: &std::path::Path) -> String {
+    file_path.extension().unwrap_or_default().to_string_lossy().to_lowercase()
+}
+
+fn parse_color<'a>(mut values: impl Iterator<Item = &'a str>) -> Option<[u8; 3]> {
+    let mut color = [0; 3];
+    for channel in &mut color {
+        *channel = values.next()?.parse().ok()?;
+    }
+    Some(color)
+}
+
+fn read_jasc_palette(file_path: &std::path::Path, string: &str) -> anyhow::Result<Vec<[u8; 3]>> {
+    let mut lines = string.lines().map(|x| x.trim());
+    anyhow::ensure!(
+        lines.next() == Some(JASC_PALETTE_HEADER) && lines.next() == Some(JASC_PALETTE_VERSION),
+        "{} is not a JASC palette file",
+        file_path.display()
+    );
+    let color_count: usize = lines
+        .next()
+        .and_then(|x| x.parse().ok())
+        .with_context(|| format!("Failed to read color count of {}", file_path.display()))?;
+
+    let mut palette = Vec::new();
+    for (i, line) in lines.filter(|x| !x.is_empty()).enumerate() {
+        let color = parse_color(line.split_whitespace())
+            .with_context(|| format!("Failed to read color {} of {}", i, file_path.display()))?;
+        palette.push(color);
+    }
+    anyhow::ensure!(
+        palette.len() == color_count,
+        "{} has {} colors but lists {}",
+        file_path.display(),
+        color_count,
+        palette.len()
+    );
+    Ok(palette)
+}
+
+fn read_gimp_palette(file_path: &std::path::Path, string: &str) -> anyhow::Result<Vec<[u8; 3]>> {
+    let mut lines = string.lines().map(|x| x.trim());
+    anyhow::ensure!(
+        lines.next() == Some(GIMP_PALETTE_HEADER),
+        "{} is not a GIMP palette file",
+        file_path.display()
+    );
+
+    // Colors can be followed by a name, and the name and column count of the palette can come before them
+    let mut palette = Vec::new();
+    for (i, line) in lines
+        .filter(|x| !x.is_empty() && !x.starts_with('#') && !x.starts_with("Name:") && !x.starts_with("Columns:"))
+        .enumerate()
+    {
+        let color = parse_color(line.split_whitespace())
+            .with_context(|| format!("Failed to read color {} of {}", i, file_path.display()))?;
+        palette.push(color);
+    }
+    Ok(palette)
+}
+
+// Adobe color tables can end with the number of colors used and a transparent index, the other entries are still
+// stored but unused
+fn read_adobe_color_table(file_path: &std::path::Path, data: &[u8]) -> anyhow::Result<Vec<[u8; 3]>> {
+    const COLOR_TABLE_SIZE: usize = 768;
+    const COLOR_TABLE_WITH_COUNT_SIZE: usize = 772;
+    anyhow::ensure!(
+        data.len() == COLOR_TABLE_SIZE || data.len() == COLOR_TABLE_WITH_COUNT_SIZE,
+        "{} is not an Adobe color table file",
+        file_path.display()
+    );
+    Ok(data[..COLOR_TABLE_SIZE].chunks_exact(3).map(|x| [x[0], x[1], x[2]]).collect())
+}
+
+pub fn read(file_path: &std::path::Path) -> anyhow::Result<Vec<[u8; 3]>> {
+    let palette = match extension(file_path).as_str() {
+        "act" => {
+            let data = std::fs::read(file_path).with_context(|| error::file_read_error(file_path))?;
+            read_adobe_color_table(file_path, &data)?
+        }
+        extension => {
+            let string = std::fs::read_to_string(file_path).with_context(|| error::file_read_error(file_path))?;
+            match extension {
+                "pal" => read_jasc_palette(file_path, &string)?,
+                "gpl" => read_gimp_palette(file_path, &string)?,
+                _ => serde_json::from_str::<Vec<[u8; 3]>>(&string)
+                    .with_context(|| format!("Failed to deserialize json file {}", file_path.display()))?,
+            }
+        }
+    };
+    anyhow::ensure!(
+        palette.len() == usize::from(palt::PALT_COLOR_ENTRY_COUNT),
+        format!(
+            "{} does not have {} colors",
+            file_path.display(),
+            palt::PALT_COLOR_ENTRY_COUNT
+        )
+    );
+    Ok(palette)
+}
+
+pub fn write(file_path: &std::path::Path, palette: &[[u8; 3]]) -> anyhow::Result<()> {
+    use std::fmt::Write;
+
+    let data = match extension(file_path).as_str() {
+        "pal" => {
+            let mut string = format!(
+                "{}\r\n{}\r\n{}\r\n",
+                JASC_PALETTE_HEADER,
+                JASC_PALETTE_VERSION,
+                palette.len()
+            );
+            for color in palette {
+                write!(string, "{} {} {}\r\n", color[0], color[1], color[2]).unwrap();
+            }
+            string.into_bytes()
+        }
+        "gpl" => {
+            let name = file_path.file_stem().unwrap_or_default().to_string_lossy();
+            let mut string = format!("{}\nName: {}\nColumns: 16\n#\n", GIMP_PALETTE_HEADER, name);
+            for (i, color) in palette.iter().enumerate() {
+                writeln!(string, "{:3} {:3} {:3}\tIndex {}", color[0], color[1], color[2], i).unwrap();
+            }
+            string.into_bytes()
+        }
+        "act" => palette.concat(),
+        "json" => serde_json::to_vec(palette)
+            .with_context(|| format!("Failed to serialize json file {}", file_path.display()))?,
+        _ => anyhow::bail!(
+            "{} is not a palette file, use one of the extensions {}",
+            file_path.display(),
+            PALETTE_FILE_EXTENSIONS.join(", ")
+        ),
+    };
+    std::fs::write(file_path, data).with_context(|| error::file_write_error(file_path))
+}
+
+// Palettes of an object are the PALT chunks it compiles to, including those of quantized truecolor sprites
+fn read_description_palt_chunks(description_file_path: &std::path::Path) -> anyhow::Result<Vec<iff::IffChunk>> {
+    let mut iff_description = iff_description::IffDescription::open(description_file_path)
+        .with_context(|| format!("Failed to open xml file {}", description_file_path.display()))?;
+
+    let source_directory = description_file_path.parent().with_context(|| {
+        format!(
+            "Failed to get source directory from xml file path {}",
+            description_file_path.display()
+        )
+    })?;
+
+    let object_name = description_file_path.file_stem().unwrap_or_default().to_string_lossy();
+    xml_updater::replace_manifest_sprites(
+        &mut iff_description,
+        description_file_path,
+        source_directory,
+        &object_name,
+        None,
+    )?;
+    let mut iff_description = iff_description.validate()?;
+
+    let sprite_quantization = splitter::sprite_quantization(source_directory, &object_name)?;
+
+    let mut sprite_images = sprite_image::SpriteImages::default();
+    iff_description.update_sprite_positions(source_directory, &sprite_images)?;
+    sprite_images.quantize_truecolor_sprites(
+        source_directory,
+        &iff_description.sprites.sprites,
+        iff_description.channel_generation(),
+        &sprite_quantization,
+    )?;
+    palt::create_palt_chunks(
+        source_directory,
+        &object_name,
+        &iff_description.sprites.sprites,
+        &sprite_images,
+    )
+}
+
+pub fn export(
+    input_file_path: &std::path::Path,
+    output_file_path: &std::path::Path,
+    palette_id: Option<i16>,
+) -> anyhow::Result<()> {
+    let palette = match extension(input_file_path).as_str() {
+        "bmp" | "png" => sprite_image::SpriteImages::default().read_palette(input_file_path)?,
+        extension => {
+            let mut palt_chunks = match extension {
+                "iff" => iff::read_chunks(input_file_path, b"PALT")?,
+                "xml" | "toml" => read_description_palt_chunks(input_file_path)?,
+                _ => anyhow::bail!(
+                    "Failed to read a palette from {}, it is not a bmp, png, iff, xml or toml file",
+                    input_file_path.display()
+                ),
+            };
+            palt_chunks.sort_by_key(|x| x.header.id().as_i16());
+
+            // The palette ID can be left out when there is only one palette
+            let palt_chunk = match palette_id {
+                Some(palette_id) => {
+                    palt_chunks.iter().find(|x| x.header.id().as_i16() == palette_id).with_context(|| {
+                        format!("Failed to find palette {} in {}", palette_id, input_file_path.display())
+                    })?
+                }
+                None => {
+                    anyhow::ensure!(
+                        !palt_chunks.is_empty(),
+                        "Failed to find any palettes in {}",
+                        input_file_path.display()
+                    );
+                    anyhow::ensure!(
+                        palt_chunks.len() == 1,
+                        "{} has {} palettes, choose one with --palette-id: {}",
+                        input_file_path.display(),
+                        palt_chunks.len(),
+                        palt_chunks.iter().map(|x| x.header.id().as_i16().to_string()).collect::<Vec<_>>().join(", ")
+                    );
+                    &palt_chunks[0]
+                }
+            };
+            palt::read_palt_chunk(palt_chunk)?
+        }
+    };
+    write(output_file_path, &palette)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_directory(name: &str) -> std::path::PathBuf {
+        let directory = std::env::temp_dir().join(format!("ts1-compiler-palette-file-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    fn test_palette() -> Vec<[u8; 3]> {
+        (0..palt::PALT_COLOR_ENTRY_COUNT).map(|x| [x as u8, 255 - x as u8, (x * 7) as u8]).collect()
+    }
+
+    #[test]
+    fn write_and_read_every_format() {
+        let directory = test_directory("round-trip");
+        let palette = test_palette();
+        for extension in PALETTE_FILE_EXTENSIONS {
+            let file_path = directory.join("palette").with_extension(extension);
+            write(&file_path, &palette).unwrap();
+            assert_eq!(read(&file_path).unwrap(), palette, "{}", extension);
+        }
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn read_gimp_palette_with_name_columns_and_comments() {
+        let mut string = "GIMP Palette\nName: Chair\nColumns: 16\n# a comment\n\n".to_owned();
+        string += "255   0   0\tRed\n";
+        string += "  0 255   0\n";
+        let palette = read_gimp_palette(std::path::Path::new("palette.gpl"), &string).unwrap();
+        assert_eq!(palette, vec![[255, 0, 0], [0, 255, 0]]);
+    }
+
+    #[test]
+    fn read_adobe_color_table_with_color_count() {
+        let palette = test_palette();
+        let mut data = palette.concat();
+        data.extend_from_slice(&[0, 16, 0xff, 0xff]);
+        assert_eq!(data.len(), 772);
+        assert_eq!(
+            read_adobe_color_table(std::path::Path::new("palette.act"), &data).unwrap(),
+            palette
+        );
+    }
+
+    #[test]
+    fn reject_wrong_color_count() {
+        let directory = test_directory("color-count");
+        let file_path = directory.join("palette").with_extension("json");
+        write(&file_path, &test_palette()[..16]).unwrap();
+        assert!(read(&file_path).is_err());
+
+        let string = "JASC-PAL\n0100\n3\n0 0 0\n255 255 255\n";
+        assert!(read_jasc_palette(std::path::Path::new("palette.pal"), string).is_err());
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
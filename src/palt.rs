@@ -1,31 +1,60 @@
-use crate::error;
 use crate::iff;
+use crate::palette_file;
 use crate::spr;
+use crate::sprite_image;
 
 use anyhow::Context;
 
 pub const PALT_COLOR_ENTRY_COUNT: u16 = 256;
 
-fn create_palt_chunk(palette_id: iff::IffChunkId, sprite_path: &std::path::Path) -> anyhow::Result<iff::IffChunk> {
+// A palette file named after the object and palette ID next to the object's description replaces the palette
+// stored in the color sprites. It is kept out of the split sprites, which are deleted when splitting again.
+pub fn read_palette(
+    source_directory: &std::path::Path,
+    object_name: &str,
+    palette_id: iff::IffChunkId,
+    sprite_path: &std::path::Path,
+    sprite_images: &sprite_image::SpriteImages,
+) -> anyhow::Result<Vec<[u8; 3]>> {
+    let palette_file_path = palette_file::PALETTE_FILE_EXTENSIONS
+        .iter()
+        .map(|x| source_directory.join(format!("{} - palette {}.{}", object_name, palette_id.as_i16(), x)))
+        .find(|x| x.is_file());
+    match palette_file_path {
+        Some(palette_file_path) => palette_file::read(&palette_file_path),
+        None => sprite_images.read_palette(sprite_path),
+    }
+}
+
+pub fn read_palt_chunk(palt_chunk: &iff::IffChunk) -> anyhow::Result<Vec<[u8; 3]>> {
+    const PALT_HEADER_SIZE: usize = 16;
+    let colors = palt_chunk.data.get(PALT_HEADER_SIZE..).unwrap_or_default();
+    let color_count = palt_chunk
+        .data
+        .get(4..8)
+        .map(|x| u32::from_le_bytes(x.try_into().unwrap()))
+        .context("Failed to read color count of PALT chunk")?;
+    anyhow::ensure!(
+        usize::try_from(color_count).unwrap() * 3 <= colors.len(),
+        "PALT chunk {} has fewer colors than its color count",
+        palt_chunk.header.id().as_i16()
+    );
+
+    let mut palette: Vec<_> = colors
+        .chunks_exact(3)
+        .take(usize::try_from(color_count).unwrap())
+        .map(|x| [x[0], x[1], x[2]])
+        .collect();
+    palette.resize(usize::from(PALT_COLOR_ENTRY_COUNT), [0, 0, 0]);
+    Ok(palette)
+}
+
+fn create_palt_chunk(palette_id: iff::IffChunkId, palette: &[[u8; 3]]) -> anyhow::Result<iff::IffChunk> {
     const PALT_CHUNK_DATA_SIZE: usize = 784;
     const PALT_VERSION: u32 = 1;
 
     let palt_chunk_header = iff::IffChunkHeader::new(b"PALT", PALT_CHUNK_DATA_SIZE, palette_id, "")?;
 
-    let bmp_buffer = std::fs::File::open(sprite_path).with_context(|| error::file_read_error(sprite_path))?;
-    let bmp_buffer = std::io::BufReader::new(&bmp_buffer);
-    let sprite_bmp =
-        image::codecs::bmp::BmpDecoder::new(bmp_buffer).with_context(|| error::file_read_error(sprite_path))?;
-
-    let palette = sprite_bmp
-        .get_palette()
-        .with_context(|| format!("{} is not in 8-bit color", sprite_path.display()))?
-        .to_vec();
-    anyhow::ensure!(
-        palette.len() == usize::from(PALT_COLOR_ENTRY_COUNT),
-        format!("{} does not have a 256 color palette", &sprite_path.display())
-    );
-
     let palette: Vec<_> = palette.iter().flat_map(|entry| [entry[0], entry[1], entry[2]]).collect();
 
     let mut palt_data = std::vec::Vec::new();
@@ -44,28 +73,37 @@ fn create_palt_chunk(palette_id: iff::IffChunkId, sprite_path: &std::path::Path)
 
 pub fn create_palt_chunks(
     source_directory: &std::path::Path,
+    object_name: &str,
     sprites: &[spr::Sprite],
+    sprite_images: &sprite_image::SpriteImages,
 ) -> anyhow::Result<Vec<iff::IffChunk>> {
-    let mut palt_chunks = std::collections::HashMap::new();
+    // Chunks are kept in the order their palettes are first used, so that the iff file is always written the same
+    let mut palette_ids = std::collections::HashSet::new();
+    let mut palt_chunks = Vec::new();
 
     for sprite in sprites {
-        if sprite.sprite_type == spr::SpriteType::Spr1 {
+        if sprite.sprite_type == spr::SpriteType::Spr1 || !palette_ids.insert(sprite.palette_chunk_id) {
+            continue;
+        }
+        if let Some(palette) = sprite_images.quantized_palette(sprite.palette_chunk_id) {
+            palt_chunks.push(create_palt_chunk(sprite.palette_chunk_id, palette)?);
             continue;
         }
-        match palt_chunks.entry(sprite.palette_chunk_id) {
-            std::collections::hash_map::Entry::Occupied(_) => (),
-            std::collections::hash_map::Entry::Vacant(entry) => {
-                let sprite_frame = sprite
-                    .sprite_frames
-                    .first()
-                    .with_context(|| format!("Failed to find color channel in sprite {}", sprite.chunk_label))?;
-                let color_sprite_file_path = source_directory.join(
-                    sprite_frame.sprite_channel_file_path_relative(spr::SpriteChannelType::Color, sprite.chunk_id)?,
-                );
-                entry.insert(create_palt_chunk(sprite.palette_chunk_id, &color_sprite_file_path)?);
-            }
-        };
+        let sprite_frame = sprite
+            .sprite_frames
+            .first()
+            .with_context(|| format!("Failed to find color channel in sprite {}", sprite.chunk_label))?;
+        let color_sprite_file_path = source_directory
+            .join(sprite_frame.sprite_channel_file_path_relative(spr::SpriteChannelType::Color, sprite.chunk_id)?);
+        let palette = read_palette(
+            source_directory,
+            object_name,
+            sprite.palette_chunk_id,
+            &color_sprite_file_path,
+            sprite_images,
+        )?;
+        palt_chunks.push(create_palt_chunk(sprite.palette_chunk_id, &palette)?);
     }
 
-    Ok(palt_chunks.into_values().collect())
+    Ok(palt_chunks)
 }
@@ -0,0 +1,297 @@
+use crate::dgrp;
+use crate::error;
+use crate::iff_description;
+use crate::palt;
+use crate::splitter;
+use crate::spr;
+use crate::sprite;
+use crate::sprite_image;
+use crate::xml_updater;
+
+use anyhow::Context;
+
+const CELL_PADDING: i32 = 8;
+
+struct PlacedSprite {
+    left: i32,
+    top: i32,
+    width: i32,
+    height: i32,
+    flipped: bool,
+    colors: Vec<[u8; 3]>,
+    depth: Vec<u8>,
+    alpha: Vec<u8>,
+}
+
+struct Bounds {
+    left: i32,
+    top: i32,
+    right: i32,
+    bottom: i32,
+}
+
+impl Bounds {
+    fn union<'a>(placed_sprites: impl Iterator<Item = &'a PlacedSprite>) -> Option<Bounds> {
+        placed_sprites.fold(None, |bounds: Option<Bounds>, x| {
+            let sprite_bounds = Bounds {
+                left: x.left,
+                top: x.top,
+                right: x.left + x.width,
+                bottom: x.top + x.height,
+            };
+            Some(match bounds {
+                Some(bounds) => Bounds {
+                    left: bounds.left.min(sprite_bounds.left),
+                    top: bounds.top.min(sprite_bounds.top),
+                    right: bounds.right.max(sprite_bounds.right),
+                    bottom: bounds.bottom.max(sprite_bounds.bottom),
+                },
+                None => sprite_bounds,
+            })
+        })
+    }
+}
+
+fn place_sprites(
+    source_directory: &std::path::Path,
+    object_name: &str,
+    iff_description: &iff_description::IffDescription,
+    sprite_images: &sprite_image::SpriteImages,
+    draw_group_item_list: &dgrp::DrawGroupItemList,
+) -> anyhow::Result<Vec<PlacedSprite>> {
+    let mut placed_sprites = Vec::new();
+    for draw_group_item in &draw_group_item_list.draw_group_items {
+        let sprite = iff_description
+            .sprites
+            .sprites
+            .iter()
+            .find(|x| x.chunk_id == draw_group_item.sprite_chunk_id && x.sprite_type == spr::SpriteType::Spr2)
+            .with_context(|| format!("Failed to find sprite {}", draw_group_item.sprite_chunk_id.as_i16()))?;
+        let frame =
+            sprite.sprite_frames.iter().find(|x| x.index == draw_group_item.sprite_index).with_context(|| {
+                format!(
+                    "Failed to find frame {} of sprite {}",
+                    draw_group_item.sprite_index.as_i32(),
+                    sprite.chunk_id.as_i16()
+                )
+            })?;
+        if frame.cropped_width == 0 || frame.cropped_height == 0 {
+            continue;
+        }
+
+        let palette = match sprite_images.quantized_palette(sprite.palette_chunk_id) {
+            Some(palette) => palette.clone(),
+            None => {
+                let color_sprite_file_path = source_directory
+                    .join(frame.sprite_channel_file_path_relative(spr::SpriteChannelType::Color, sprite.chunk_id)?);
+                palt::read_palette(
+                    source_directory,
+                    object_name,
+                    sprite.palette_chunk_id,
+                    &color_sprite_file_path,
+                    sprite_images,
+                )?
+            }
+        };
+        let frame_channels = frame.read_channels(
+            source_directory,
+            sprite.chunk_id,
+            iff_description.channel_generation(),
+            sprite_images,
+        )?;
+
+        // Sprite offsets place the bottom left of the frame relative to the center of the object
+        let height = i32::from(frame.cropped_height);
+        placed_sprites.push(PlacedSprite {
+            left: draw_group_item.sprite_offset_x,
+            top: draw_group_item.sprite_offset_y - height,
+            width: i32::from(frame.cropped_width),
+            height,
+            flipped: draw_group_item.flags & 0b1 != 0,
+            colors: frame_channels.color.iter().map(|x| palette[usize::from(*x)]).collect(),
+            depth: frame_channels.depth,
+            alpha: frame_channels.alpha,
+        });
+    }
+    Ok(placed_sprites)
+}
+
+// Sprites are drawn with their depth, lower values being closer to the camera, and blended with their alpha
+fn draw_sprites(
+    preview: &mut image::RgbaImage,
+    depth_buffer: &mut image::GrayImage,
+    placed_sprites: &[PlacedSprite],
+    origin_x: i32,
+    origin_y: i32,
+) {
+    for placed_sprite in placed_sprites {
+        for y in 0..placed_sprite.height {
+            for x in 0..placed_sprite.width {
+                let source_x = if placed_sprite.flipped {
+                    placed_sprite.width - 1 - x
+                } else {
+                    x
+                };
+                let index = usize::try_from(y * placed_sprite.width + source_x).unwrap();
+                let alpha = placed_sprite.alpha[index];
+                let depth = placed_sprite.depth[index];
+                if alpha == 0 {
+                    continue;
+                }
+
+                let preview_x = u32::try_from(origin_x + placed_sprite.left + x).unwrap();
+                let preview_y = u32::try_from(origin_y + placed_sprite.top + y).unwrap();
+                if depth > depth_buffer.get_pixel(preview_x, preview_y)[0] {
+                    continue;
+                }
+                depth_buffer.put_pixel(preview_x, preview_y, image::Luma([depth]));
+
+                let color = placed_sprite.colors[index];
+                let background = *preview.get_pixel(preview_x, preview_y);
+                let blend = |source: u8, destination: u8| {
+                    u8::try_from(
+                        (u32::from(source) * u32::from(alpha) + u32::from(destination) * (255 - u32::from(alpha)))
+                            / 255,
+                    )
+                    .unwrap()
+                };
+                preview.put_pixel(
+                    preview_x,
+                    preview_y,
+                    image::Rgba([
+                        blend(color[0], background[0]),
+                        blend(color[1], background[1]),
+                        blend(color[2], background[2]),
+                        std::cmp::max(alpha, background[3]),
+                    ]),
+                );
+            }
+        }
+    }
+}
+
+pub fn preview(
+    xml_file_path: &std::path::Path,
+    draw_group_id: Option<i16>,
+    output_file_path: Option<&std::path::Path>,
+) -> anyhow::Result<()> {
+    let mut iff_description = iff_description::IffDescription::open(xml_file_path)
+        .with_context(|| format!("Failed to open xml file {}", xml_file_path.display()))?;
+
+    let source_directory = xml_file_path.parent().with_context(|| {
+        format!(
+            "Failed to get source directory from xml file path {}",
+            xml_file_path.display()
+        )
+    })?;
+
+    let object_name = xml_file_path.file_stem().unwrap_or_default().to_string_lossy();
+    xml_updater::replace_manifest_sprites(
+        &mut iff_description,
+        xml_file_path,
+        source_directory,
+        &object_name,
+        None,
+    )?;
+    let mut iff_description = iff_description.validate()?;
+
+    let sprite_quantization = splitter::sprite_quantization(source_directory, &object_name)?;
+
+    let mut sprite_images = sprite_image::SpriteImages::default();
+    iff_description.update_sprite_positions(source_directory, &sprite_images)?;
+    sprite_images.quantize_truecolor_sprites(
+        source_directory,
+        &iff_description.sprites.sprites,
+        iff_description.channel_generation(),
+        &sprite_quantization,
+    )?;
+
+    let draw_group = match draw_group_id {
+        Some(draw_group_id) => iff_description
+            .draw_groups
+            .draw_groups
+            .iter()
+            .find(|x| x.chunk_id.as_i16() == draw_group_id)
+            .with_context(|| format!("Failed to find draw group {}", draw_group_id))?,
+        None => iff_description.draw_groups.draw_groups.first().context("Failed to find any draw groups")?,
+    };
+
+    // The contact sheet has a row for each zoom level and a column for each rotation
+    let zoom_levels = [sprite::ZoomLevel::Zero, sprite::ZoomLevel::One, sprite::ZoomLevel::Two];
+    let rotations = [
+        sprite::Rotation::NorthWest,
+        sprite::Rotation::NorthEast,
+        sprite::Rotation::SouthEast,
+        sprite::Rotation::SouthWest,
+    ];
+
+    let mut rows = Vec::new();
+    for zoom_level in zoom_levels {
+        let mut cells = Vec::new();
+        for rotation in rotations {
+            let draw_group_item_list = draw_group
+                .draw_group_item_lists
+                .iter()
+                .find(|x| x.zoom_level == zoom_level && x.rotation == rotation)
+                .with_context(|| {
+                    format!(
+                        "Failed to find {} {} item list in draw group {} {}",
+                        zoom_level,
+                        rotation,
+                        draw_group.chunk_id.as_i16(),
+                        draw_group.chunk_label
+                    )
+                })?;
+            cells.push(place_sprites(
+                source_directory,
+                &object_name,
+                &iff_description,
+                &sprite_images,
+                draw_group_item_list,
+            )?);
+        }
+        let bounds = Bounds::union(cells.iter().flatten()).unwrap_or(Bounds {
+            left: 0,
+            top: 0,
+            right: 0,
+            bottom: 0,
+        });
+        rows.push((cells, bounds));
+    }
+
+    let cell_width = |bounds: &Bounds| bounds.right - bounds.left + CELL_PADDING * 2;
+    let cell_height = |bounds: &Bounds| bounds.bottom - bounds.top + CELL_PADDING * 2;
+    let preview_width = rows.iter().map(|x| cell_width(&x.1) * 4).max().unwrap_or(0);
+    let preview_height = rows.iter().map(|x| cell_height(&x.1)).sum::<i32>();
+
+    let mut preview = image::RgbaImage::new(
+        u32::try_from(preview_width).unwrap(),
+        u32::try_from(preview_height).unwrap(),
+    );
+    let mut depth_buffer = image::GrayImage::from_pixel(preview.width(), preview.height(), image::Luma([255]));
+
+    let mut row_top = 0;
+    for (cells, bounds) in &rows {
+        for (column, placed_sprites) in cells.iter().enumerate() {
+            let cell_left = cell_width(bounds) * i32::try_from(column).unwrap();
+            draw_sprites(
+                &mut preview,
+                &mut depth_buffer,
+                placed_sprites,
+                cell_left + CELL_PADDING - bounds.left,
+                row_top + CELL_PADDING - bounds.top,
+            );
+        }
+        row_top += cell_height(bounds);
+    }
+
+    let output_file_path = match output_file_path {
+        Some(output_file_path) => output_file_path.to_owned(),
+        None => xml_file_path.with_file_name(format!(
+            "{} - preview {}.png",
+            xml_file_path.file_stem().unwrap_or_default().to_string_lossy(),
+            draw_group.chunk_id.as_i16()
+        )),
+    };
+    preview.save(&output_file_path).with_context(|| error::file_write_error(&output_file_path))
+}
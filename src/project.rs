@@ -0,0 +1,460 @@
+use crate::error;
+use crate::iff_description;
+use crate::xml_document;
+
+use anyhow::Context;
+
+const ROOT_ELEMENT_NAME: &str = "objectsexportedfromthesims";
+
+// The object description sections and the name of the elements they contain
+const SECTIONS: &[(&str, &str)] = &[
+    ("objectdefinitions", "objectdefinition"),
+    ("slots", "slot"),
+    ("drawgroups", "drawgroup"),
+    ("sprites", "sprite"),
+];
+
+// Transmogrifier export settings, which only have to be listed in a project manifest when they differ
+const TRANSMOGRIFIER_DEFAULTS: &[(&str, &str)] = &[
+    ("exportobjectdefinitions", "1"),
+    ("exportslots", "1"),
+    ("exportdrawgroups", "1"),
+    ("exportbitmaps", "1"),
+    ("exportsprites", "1"),
+    ("justchangecolors", "0"),
+    ("exportallzooms", "1"),
+    ("smoothsmallzoomcolors", "0"),
+    ("smoothsmallzoomedges", "0"),
+    ("exportexpanded", "1"),
+    ("exportp", "1"),
+    ("exportz", "1"),
+    ("generatez", "0"),
+    ("generatezfar", "0"),
+    ("exporta", "1"),
+    ("generatea", "0"),
+    ("generateasoft", "0"),
+    ("compressbitmaps", "0"),
+    ("createsubdirectories", "0"),
+    ("thingstodo", "0"),
+];
+
+// Attributes which are kept as strings even if they look like numbers
+const STRING_ATTRIBUTES: &[&str] = &["name", "filename", "objectfilename"];
+
+pub fn is_project_manifest(file_path: &std::path::Path) -> bool {
+    file_path.extension().is_some_and(|x| x.eq_ignore_ascii_case("toml"))
+}
+
+pub fn description_file_path(source_directory: &std::path::Path, object_name: &str) -> std::path::PathBuf {
+    let project_file_path = source_directory.join(format!("{}.toml", object_name));
+    if project_file_path.is_file() {
+        project_file_path
+    } else {
+        source_directory.join(format!("{}.xml", object_name))
+    }
+}
+
+fn read_table(
+    project_file_path: &std::path::Path,
+    included_by: &mut Vec<std::path::PathBuf>,
+) -> anyhow::Result<toml::Table> {
+    let canonical_file_path =
+        project_file_path.canonicalize().with_context(|| error::file_read_error(project_file_path))?;
+    anyhow::ensure!(
+        !included_by.contains(&canonical_file_path),
+        "{} includes itself",
+        project_file_path.display()
+    );
+
+    let toml_string =
+        std::fs::read_to_string(project_file_path).with_context(|| error::file_read_error(project_file_path))?;
+    let mut table = toml_string
+        .parse::<toml::Table>()
+        .with_context(|| format!("Failed to parse project manifest {}", project_file_path.display()))?;
+
+    let include_file_paths = match table.remove("include") {
+        Some(toml::Value::Array(include_file_paths)) => include_file_paths,
+        Some(toml::Value::String(include_file_path)) => vec![toml::Value::String(include_file_path)],
+        Some(_) => anyhow::bail!(
+            "include in {} must be a file path or a list of file paths",
+            project_file_path.display()
+        ),
+        None => Vec::new(),
+    };
+
+    included_by.push(canonical_file_path);
+    let mut merged_table = toml::Table::new();
+    for include_file_path in include_file_paths {
+        let include_file_path = include_file_path.as_str().with_context(|| {
+            format!(
+                "include in {} must be a file path or a list of file paths",
+                project_file_path.display()
+            )
+        })?;
+        let include_file_path = project_file_path.with_file_name(include_file_path);
+        let included_table = read_table(&include_file_path, included_by)
+            .with_context(|| format!("Failed to include {}", include_file_path.display()))?;
+        merge_table(&mut merged_table, included_table);
+    }
+    included_by.pop();
+
+    merge_table(&mut merged_table, table);
+    Ok(merged_table)
+}
+
+// Tables are merged key by key, lists of elements are appended to and other values are replaced
+fn merge_table(table: &mut toml::Table, other: toml::Table) {
+    for (key, value) in other {
+        match (table.get_mut(&key), value) {
+            (Some(toml::Value::Table(table)), toml::Value::Table(other)) => merge_table(table, other),
+            (Some(toml::Value::Array(array)), toml::Value::Array(mut other)) => array.append(&mut other),
+            (_, value) => {
+                table.insert(key, value);
+            }
+        }
+    }
+}
+
+fn attribute_value(key: &str, value: &toml::Value) -> anyhow::Result<String> {
+    match value {
+        toml::Value::String(value) => Ok(value.clone()),
+        toml::Value::Integer(value) => Ok(value.to_string()),
+        toml::Value::Float(value) => Ok(value.to_string()),
+        toml::Value::Boolean(value) => Ok(if *value { "1" } else { "0" }.to_owned()),
+        _ => anyhow::bail!("{} must be a string, number or boolean", key),
+    }
+}
+
+fn write_element(
+    writer: &mut quick_xml::Writer<Vec<u8>>,
+    name: &str,
+    table: &toml::Table,
+    defaults: &toml::Table,
+) -> anyhow::Result<()> {
+    let mut attributes: Vec<(&str, String)> = Vec::new();
+    let mut children: Vec<(&str, &toml::Table)> = Vec::new();
+    for (key, value) in table {
+        match value {
+            toml::Value::Table(child) => children.push((key, child)),
+            toml::Value::Array(array) if array.iter().all(|x| x.is_table()) => {
+                children.extend(array.iter().filter_map(|x| x.as_table()).map(|x| (key.as_str(), x)));
+            }
+            value => attributes.push((key, attribute_value(key, value)?)),
+        }
+    }
+    if let Some(toml::Value::Table(element_defaults)) = defaults.get(name) {
+        for (key, value) in element_defaults {
+            if !table.contains_key(key) {
+                attributes.push((key, attribute_value(key, value)?));
+            }
+        }
+    }
+
+    let mut element = quick_xml::events::BytesStart::new(name);
+    for (key, value) in &attributes {
+        element.push_attribute((*key, value.as_str()));
+    }
+    if children.is_empty() {
+        writer.write_event(quick_xml::events::Event::Empty(element))?;
+    } else {
+        writer.write_event(quick_xml::events::Event::Start(element))?;
+        for (child_name, child) in children {
+            write_element(writer, child_name, child, defaults).with_context(|| format!("in {}", name))?;
+        }
+        writer.write_event(quick_xml::events::Event::End(quick_xml::events::BytesEnd::new(name)))?;
+    }
+    Ok(())
+}
+
+fn to_xml(mut table: toml::Table) -> anyhow::Result<String> {
+    let defaults = match table.remove("defaults") {
+        Some(toml::Value::Table(defaults)) => defaults,
+        Some(_) => anyhow::bail!("defaults must be a table of element names"),
+        None => toml::Table::new(),
+    };
+
+    let mut root = toml::Table::new();
+    for (key, value) in TRANSMOGRIFIER_DEFAULTS {
+        root.insert((*key).to_owned(), toml::Value::String((*value).to_owned()));
+    }
+    for (section_name, element_name) in SECTIONS {
+        let elements = match table.remove(*element_name) {
+            Some(toml::Value::Array(elements)) => elements,
+            Some(_) => anyhow::bail!("{} must be a list of tables, use [[{}]]", element_name, element_name),
+            None => Vec::new(),
+        };
+        let mut section = toml::Table::new();
+        if !elements.is_empty() {
+            section.insert((*element_name).to_owned(), toml::Value::Array(elements));
+        }
+        root.insert((*section_name).to_owned(), toml::Value::Table(section));
+    }
+    for (key, value) in table {
+        anyhow::ensure!(!value.is_table() && !value.is_array(), "unknown section {}", key);
+        root.insert(key, value);
+    }
+
+    let mut writer = quick_xml::Writer::new(Vec::new());
+    write_element(&mut writer, ROOT_ELEMENT_NAME, &root, &defaults)?;
+    Ok(String::from_utf8(writer.into_inner())?)
+}
+
+pub fn read_xml(project_file_path: &std::path::Path) -> anyhow::Result<String> {
+    let table = read_table(project_file_path, &mut Vec::new())?;
+    to_xml(table).with_context(|| format!("Failed to read project manifest {}", project_file_path.display()))
+}
+
+fn toml_value(key: &str, value: &str) -> toml_edit::Value {
+    if !STRING_ATTRIBUTES.contains(&key) {
+        if let Ok(integer) = value.parse::<i64>() {
+            if integer.to_string() == value {
+                return integer.into();
+            }
+        }
+        // Numbers are only converted when they are written back the same, so that the xml is kept as it was
+        if let Ok(float) = value.parse::<f64>() {
+            if float.is_finite() && float.to_string() == value {
+                return float.into();
+            }
+        }
+    }
+    value.into()
+}
+
+fn element_to_table(element: &xml_document::XmlElement) -> toml_edit::Table {
+    let mut table = toml_edit::Table::new();
+    for (key, value) in element.attributes() {
+        table.insert(key, toml_edit::value(toml_value(key, value)));
+    }
+
+    let mut child_names: Vec<&str> = Vec::new();
+    for child in element.children() {
+        if !child_names.contains(&child.name()) {
+            child_names.push(child.name());
+        }
+    }
+    for child_name in child_names {
+        let children: Vec<_> = element.children().iter().filter(|x| x.name() == child_name).collect();
+        if children.iter().all(|x| x.children().is_empty()) {
+            // Elements without children of their own are written as a list of inline tables, one per line
+            let mut array = toml_edit::Array::new();
+            for child in children {
+                let mut inline_table = toml_edit::InlineTable::new();
+                for (key, value) in child.attributes() {
+                    inline_table.insert(key, toml_value(key, value));
+                }
+                array.push(inline_table);
+            }
+            for value in array.iter_mut() {
+                value.decor_mut().set_prefix("\n  ");
+            }
+            array.set_trailing("\n");
+            array.set_trailing_comma(true);
+            table.insert(child_name, toml_edit::value(array));
+        } else {
+            let mut array_of_tables = toml_edit::ArrayOfTables::new();
+            for child in children {
+                array_of_tables.push(element_to_table(child));
+            }
+            table.insert(child_name, toml_edit::Item::ArrayOfTables(array_of_tables));
+        }
+    }
+    table
+}
+
+pub fn to_toml(document: &xml_document::XmlDocument) -> anyhow::Result<String> {
+    let root = document.root();
+    anyhow::ensure!(
+        root.name() == ROOT_ELEMENT_NAME,
+        "expected root element {}, found {}",
+        ROOT_ELEMENT_NAME,
+        root.name()
+    );
+
+    let mut project = toml_edit::DocumentMut::new();
+    for (key, value) in root.attributes() {
+        let is_default = TRANSMOGRIFIER_DEFAULTS.iter().any(|(default_key, default_value)| {
+            *default_key == key && xml_document::attribute_values_equal(default_value, value)
+        });
+        if !is_default {
+            project.insert(key, toml_edit::value(toml_value(key, value)));
+        }
+    }
+
+    for section in root.children() {
+        let (_, element_name) = SECTIONS
+            .iter()
+            .find(|(section_name, _)| *section_name == section.name())
+            .with_context(|| format!("unknown section {}", section.name()))?;
+        let mut array_of_tables = toml_edit::ArrayOfTables::new();
+        for element in section.children() {
+            anyhow::ensure!(
+                element.name() == *element_name,
+                "expected {} in {}, found {}",
+                element_name,
+                section.name(),
+                element.name()
+            );
+            array_of_tables.push(element_to_table(element));
+        }
+        if !array_of_tables.is_empty() {
+            project.insert(element_name, toml_edit::Item::ArrayOfTables(array_of_tables));
+        }
+    }
+
+    Ok(project.to_string())
+}
+
+pub fn convert(input_file_path: &std::path::Path, output_file_path: &std::path::Path) -> anyhow::Result<()> {
+    if is_project_manifest(output_file_path) {
+        anyhow::ensure!(
+            !is_project_manifest(input_file_path),
+            "{} is already a project manifest",
+            input_file_path.display()
+        );
+
+        // Make sure the xml file describes a valid object before converting it
+        iff_description::IffDescription::open(input_file_path)
+            .with_context(|| format!("Failed to open xml file {}", input_file_path.display()))?;
+
+        let xml = std::fs::read_to_string(input_file_path).with_context(|| error::file_read_error(input_file_path))?;
+        let document = xml_document::XmlDocument::parse(&xml)
+            .with_context(|| format!("Failed to parse xml file {}", input_file_path.display()))?;
+        let toml_string = to_toml(&document)?;
+        std::fs::write(output_file_path, toml_string).with_context(|| error::file_write_error(output_file_path))
+    } else {
+        anyhow::ensure!(
+            is_project_manifest(input_file_path),
+            "either {} or {} has to be a project manifest with a .toml extension",
+            input_file_path.display(),
+            output_file_path.display()
+        );
+
+        let iff_description = iff_description::IffDescription::open(input_file_path)
+            .with_context(|| format!("Failed to open project manifest {}", input_file_path.display()))?;
+        // Attribute values are written as they are in the project manifest, not as they are parsed
+        let xml =
+            xml_document::XmlDocument::parse(&iff_description.to_xml()?)?.merge_exactly(&read_xml(input_file_path)?)?;
+        std::fs::write(output_file_path, xml).with_context(|| error::file_write_error(output_file_path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_directory(name: &str) -> std::path::PathBuf {
+        let directory = std::env::temp_dir().join(format!("ts1-compiler-project-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    // Elements with their attributes in a fixed order, as the order of attributes in the xml can change
+    fn describe_element(element: &xml_document::XmlElement, description: &mut String) {
+        let mut attributes: Vec<_> = element.attributes().collect();
+        attributes.sort();
+        *description += &format!("<{} {:?}>", element.name(), attributes);
+        for child in element.children() {
+            describe_element(child, description);
+        }
+        *description += &format!("</{}>", element.name());
+    }
+
+    fn describe_xml(xml: &str) -> String {
+        let mut description = String::new();
+        describe_element(xml_document::XmlDocument::parse(xml).unwrap().root(), &mut description);
+        description
+    }
+
+    #[test]
+    fn convert_xml_to_toml_and_back() {
+        let root_attributes: String =
+            TRANSMOGRIFIER_DEFAULTS.iter().map(|(key, value)| format!(" {}=\"{}\"", key, value)).collect();
+        let xml = format!(
+            r#"<objectsexportedfromthesims objectfilename="Downloads/Chair"{}>
+  <objectdefinitions>
+    <objectdefinition name="1.0" id="128" price="007" guid="1234"/>
+  </objectdefinitions>
+  <slots>
+  </slots>
+  <drawgroups>
+    <drawgroup name="dg" id="100">
+      <drawgroupitemlist dirflags="1" zoom="1">
+        <drawgroupitem spriteid="200" xoffset="0.000000" yoffset="1.50" zoffset="-2"/>
+      </drawgroupitemlist>
+    </drawgroup>
+  </drawgroups>
+  <sprites>
+    <sprite name="Chair" id="200" type="2">
+      <spriteframe index="0" zoom="0" rot="0">
+        <spritechannel type="p" filename="Chair - sprites/large_nw_p.bmp"/>
+      </spriteframe>
+    </sprite>
+  </sprites>
+</objectsexportedfromthesims>"#,
+            root_attributes
+        );
+
+        let directory = test_directory("round-trip");
+        let project_file_path = directory.join("Chair.toml");
+        let toml_string = to_toml(&xml_document::XmlDocument::parse(&xml).unwrap()).unwrap();
+        std::fs::write(&project_file_path, toml_string).unwrap();
+        assert_eq!(describe_xml(&read_xml(&project_file_path).unwrap()), describe_xml(&xml));
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn read_includes_and_defaults() {
+        let directory = test_directory("include");
+        std::fs::write(
+            directory.join("common.toml"),
+            r#"
+[defaults.drawgroupitem]
+xoffset = 0
+zoffset = 0
+
+[[sprite]]
+name = "Common"
+id = 100
+"#,
+        )
+        .unwrap();
+        std::fs::write(
+            directory.join("Chair.toml"),
+            r#"
+include = ["common.toml"]
+
+[[drawgroup]]
+name = "dg"
+id = 100
+drawgroupitemlist = [{ dirflags = 1, zoom = 1, drawgroupitem = [{ spriteid = 200, zoffset = 5 }] }]
+
+[[sprite]]
+name = "Chair"
+id = 200
+"#,
+        )
+        .unwrap();
+        let xml = read_xml(&directory.join("Chair.toml")).unwrap();
+        let document = xml_document::XmlDocument::parse(&xml).unwrap();
+
+        let sections = document.root().children();
+        let sprites = sections.iter().find(|x| x.name() == "sprites").unwrap().children();
+        let sprite_names: Vec<_> = sprites.iter().filter_map(|x| x.attributes().find(|x| x.0 == "name")).collect();
+        assert_eq!(sprite_names, vec![("name", "Common"), ("name", "Chair")]);
+
+        let draw_groups = sections.iter().find(|x| x.name() == "drawgroups").unwrap();
+        let draw_group_item = &draw_groups.children()[0].children()[0].children()[0];
+        let mut attributes: Vec<_> = draw_group_item.attributes().collect();
+        attributes.sort();
+        assert_eq!(
+            attributes,
+            vec![("spriteid", "200"), ("xoffset", "0"), ("zoffset", "5")]
+        );
+
+        // Manifests including each other are an error instead of being read forever
+        std::fs::write(directory.join("common.toml"), "include = [\"Chair.toml\"]\n").unwrap();
+        assert!(read_xml(&directory.join("Chair.toml")).is_err());
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
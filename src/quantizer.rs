@@ -1,3 +1,4 @@
+use crate::color_space;
 use crate::palt;
 
 pub fn posterize(color: u8, bits: u8) -> u8 {
@@ -11,74 +12,314 @@ pub fn posterize_normalized(color: f32, bits: u8) -> f32 {
 
 pub struct R5g6b5Image(image::RgbImage);
 
-pub fn dither_color_sprite_to_r5g6b5(image: image::RgbImage) -> R5g6b5Image {
-    let mut image = image::DynamicImage::ImageRgb8(image).into_rgb32f();
-    for y in 0..image.height() {
-        for x in 0..image.width() {
-            let old_pixel = *image.get_pixel(x, y);
-            let new_pixel = image::Rgb([
-                posterize_normalized(old_pixel[0], 3),
-                posterize_normalized(old_pixel[1], 2),
-                posterize_normalized(old_pixel[2], 3),
-            ]);
-
-            let error = [
-                old_pixel[0] - new_pixel[0],
-                old_pixel[1] - new_pixel[1],
-                old_pixel[2] - new_pixel[2],
-            ];
+#[derive(Copy, Clone, Debug, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Dithering {
+    None,
+    Ordered,
+    Atkinson,
+    BlueNoise,
+    #[default]
+    FloydSteinberg,
+    SerpentineFloydSteinberg,
+}
+
+// Offsets and weights of the pixels that the error of a pixel is spread to
+const FLOYD_STEINBERG_KERNEL: &[(i32, i32, f32)] = &[
+    (1, 0, 7.0 / 16.0),
+    (-1, 1, 3.0 / 16.0),
+    (0, 1, 5.0 / 16.0),
+    (1, 1, 1.0 / 16.0),
+];
+const ATKINSON_KERNEL: &[(i32, i32, f32)] = &[
+    (1, 0, 1.0 / 8.0),
+    (2, 0, 1.0 / 8.0),
+    (-1, 1, 1.0 / 8.0),
+    (0, 1, 1.0 / 8.0),
+    (1, 1, 1.0 / 8.0),
+    (0, 2, 1.0 / 8.0),
+];
+
+fn bayer_threshold(x: u32, y: u32) -> f32 {
+    // The 8x8 Bayer matrix is built by interleaving the bits of x xor y and y in reverse order
+    let xor = x ^ y;
+    let mut value = 0;
+    for bit in 0..3 {
+        value |= ((xor >> bit) & 1) << (5 - 2 * bit);
+        value |= ((y >> bit) & 1) << (4 - 2 * bit);
+    }
+    (value as f32 + 0.5) / 64.0
+}
+
+const BLUE_NOISE_SIZE: usize = 64;
+const BLUE_NOISE_SIGMA: f32 = 1.5;
 
-            image.put_pixel(x, y, new_pixel);
+// The blue noise texture is made once using the void and cluster method, which ranks every pixel by how far it is from
+// the pixels ranked before it. It wraps around its edges so that it can be tiled.
+fn blue_noise_threshold(x: u32, y: u32) -> f32 {
+    static THRESHOLDS: std::sync::OnceLock<Vec<f32>> = std::sync::OnceLock::new();
+    let thresholds = THRESHOLDS.get_or_init(|| {
+        const SIZE: usize = BLUE_NOISE_SIZE;
+        const PIXEL_COUNT: usize = SIZE * SIZE;
+
+        let kernel: Vec<f32> = (0..PIXEL_COUNT)
+            .map(|i| {
+                let dx = (i % SIZE).min(SIZE - i % SIZE) as f32;
+                let dy = (i / SIZE).min(SIZE - i / SIZE) as f32;
+                (-(dx * dx + dy * dy) / (2.0 * BLUE_NOISE_SIGMA * BLUE_NOISE_SIGMA)).exp()
+            })
+            .collect();
+        let update_energy = |energy: &mut [f32], i: usize, sign: f32| {
+            for (j, energy) in energy.iter_mut().enumerate() {
+                let dx = (j % SIZE + SIZE - i % SIZE) % SIZE;
+                let dy = (j / SIZE + SIZE - i / SIZE) % SIZE;
+                *energy += sign * kernel[dy * SIZE + dx];
+            }
+        };
+        let tightest_cluster = |pattern: &[bool], energy: &[f32]| {
+            (0..PIXEL_COUNT).filter(|i| pattern[*i]).max_by(|a, b| energy[*a].total_cmp(&energy[*b])).unwrap()
+        };
+        let largest_void = |pattern: &[bool], energy: &[f32]| {
+            (0..PIXEL_COUNT).filter(|i| !pattern[*i]).min_by(|a, b| energy[*a].total_cmp(&energy[*b])).unwrap()
+        };
 
-            if x + 1 < image.width() {
-                let pixel = image.get_pixel_mut(x + 1, y);
-                pixel[0] += error[0] * 7.0 / 16.0;
-                pixel[1] += error[1] * 7.0 / 16.0;
-                pixel[2] += error[2] * 7.0 / 16.0;
+        // A tenth of the pixels are picked with a fixed seed, so the texture is always the same
+        let initial_count = PIXEL_COUNT / 10;
+        let mut pattern = vec![false; PIXEL_COUNT];
+        let mut energy = vec![0.0; PIXEL_COUNT];
+        let mut state: u32 = 1;
+        let mut count = 0;
+        while count < initial_count {
+            state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+            let i = usize::try_from(state >> 8).unwrap() % PIXEL_COUNT;
+            if !pattern[i] {
+                pattern[i] = true;
+                update_energy(&mut energy, i, 1.0);
+                count += 1;
             }
+        }
 
-            if x != 0 && y + 1 < image.height() {
-                let pixel = image.get_pixel_mut(x - 1, y + 1);
-                pixel[0] += error[0] * 3.0 / 16.0;
-                pixel[1] += error[1] * 3.0 / 16.0;
-                pixel[2] += error[2] * 3.0 / 16.0;
+        // The picked pixels are spread out by moving the tightest cluster to the largest void until it is stable
+        for _ in 0..PIXEL_COUNT {
+            let cluster = tightest_cluster(&pattern, &energy);
+            pattern[cluster] = false;
+            update_energy(&mut energy, cluster, -1.0);
+            let void = largest_void(&pattern, &energy);
+            pattern[void] = true;
+            update_energy(&mut energy, void, 1.0);
+            if void == cluster {
+                break;
             }
+        }
 
-            if y + 1 < image.height() {
-                let pixel = image.get_pixel_mut(x, y + 1);
-                pixel[0] += error[0] * 5.0 / 16.0;
-                pixel[1] += error[1] * 5.0 / 16.0;
-                pixel[2] += error[2] * 5.0 / 16.0;
+        // The picked pixels are ranked by removing the tightest clusters, and the others by filling the largest voids
+        let mut ranks = vec![0; PIXEL_COUNT];
+        {
+            let mut pattern = pattern.clone();
+            let mut energy = energy.clone();
+            for rank in (0..initial_count).rev() {
+                let cluster = tightest_cluster(&pattern, &energy);
+                pattern[cluster] = false;
+                update_energy(&mut energy, cluster, -1.0);
+                ranks[cluster] = rank;
             }
+        }
+        for rank in initial_count..PIXEL_COUNT {
+            let void = largest_void(&pattern, &energy);
+            pattern[void] = true;
+            update_energy(&mut energy, void, 1.0);
+            ranks[void] = rank;
+        }
+
+        ranks.iter().map(|x| (*x as f32 + 0.5) / PIXEL_COUNT as f32).collect()
+    });
+    let x = usize::try_from(x).unwrap() % BLUE_NOISE_SIZE;
+    let y = usize::try_from(y).unwrap() % BLUE_NOISE_SIZE;
+    thresholds[y * BLUE_NOISE_SIZE + x]
+}
 
-            if x + 1 < image.width() && y + 1 < image.height() {
-                let pixel = image.get_pixel_mut(x + 1, y + 1);
-                pixel[0] += error[0] * 1.0 / 16.0;
-                pixel[1] += error[1] * 1.0 / 16.0;
-                pixel[2] += error[2] * 1.0 / 16.0;
+// Every pixel is replaced by the color that nearest_color picks for it, which also gives the value written to the
+// output. Ordered dithering offsets each pixel by its threshold within the threshold range of each channel first,
+// and error diffusion spreads the difference between the pixel and its new color to the pixels after it.
+fn dither<T: Copy + Default>(
+    image: &image::Rgb32FImage,
+    dithering: Dithering,
+    threshold_ranges: [std::ops::Range<f32>; 3],
+    nearest_color: impl Fn(u32, u32, [f32; 3]) -> ([f32; 3], T),
+) -> Vec<T> {
+    let (width, height) = image.dimensions();
+    let mut image = image.clone();
+    let mut output = vec![T::default(); usize::try_from(width * height).unwrap()];
+
+    let kernel = match dithering {
+        Dithering::FloydSteinberg | Dithering::SerpentineFloydSteinberg => FLOYD_STEINBERG_KERNEL,
+        Dithering::Atkinson => ATKINSON_KERNEL,
+        Dithering::None | Dithering::Ordered | Dithering::BlueNoise => &[],
+    };
+
+    for y in 0..height {
+        // Every other row is dithered from right to left, so that errors are not all spread in the same direction
+        let reverse = dithering == Dithering::SerpentineFloydSteinberg && y % 2 == 1;
+        for i in 0..width {
+            let x = if reverse { width - 1 - i } else { i };
+            let old_pixel = image.get_pixel(x, y).0;
+
+            let threshold = match dithering {
+                Dithering::Ordered => Some(bayer_threshold(x, y)),
+                Dithering::BlueNoise => Some(blue_noise_threshold(x, y)),
+                _ => None,
+            };
+            let (new_pixel, value) = match threshold {
+                Some(threshold) => nearest_color(
+                    x,
+                    y,
+                    std::array::from_fn(|c| {
+                        let range = &threshold_ranges[c];
+                        old_pixel[c] + range.start + threshold * (range.end - range.start)
+                    }),
+                ),
+                None => nearest_color(x, y, old_pixel),
+            };
+            output[usize::try_from(y * width + x).unwrap()] = value;
+
+            let error: [f32; 3] = std::array::from_fn(|c| old_pixel[c] - new_pixel[c]);
+            for (dx, dy, weight) in kernel {
+                let dx = if reverse { -dx } else { *dx };
+                let (Some(x), Some(y)) = (x.checked_add_signed(dx), y.checked_add_signed(*dy)) else {
+                    continue;
+                };
+                if x < width && y < height {
+                    let pixel = image.get_pixel_mut(x, y);
+                    pixel[0] += error[0] * weight;
+                    pixel[1] += error[1] * weight;
+                    pixel[2] += error[2] * weight;
+                }
             }
         }
     }
+    output
+}
+
+pub fn dither_color_sprite_to_r5g6b5(image: image::RgbImage, dithering: Dithering) -> R5g6b5Image {
+    let image = image::DynamicImage::ImageRgb8(image).into_rgb32f();
+    // Posterizing rounds down, so thresholds range over a whole R5G6B5 step above each color
+    let pixels = dither(
+        &image,
+        dithering,
+        [0.0..8.0 / 255.0, 0.0..4.0 / 255.0, 0.0..8.0 / 255.0],
+        |_, _, pixel| {
+            let new_pixel = [
+                posterize_normalized(pixel[0], 3),
+                posterize_normalized(pixel[1], 2),
+                posterize_normalized(pixel[2], 3),
+            ];
+            (new_pixel, new_pixel)
+        },
+    );
+    let image = image::Rgb32FImage::from_vec(image.width(), image.height(), pixels.concat()).unwrap();
     R5g6b5Image(image::DynamicImage::ImageRgb32F(image).into_rgb8())
 }
 
+// Alpha is dithered to the 5 bits kept in sprites. Fully transparent and fully opaque pixels stay exact and spread no
+// error, so that only partially transparent areas are dithered.
+pub fn dither_alpha_sprite(alpha: &image::Rgb32FImage, dithering: Dithering) -> image::Rgb32FImage {
+    let pixels = dither(
+        alpha,
+        dithering,
+        std::array::from_fn(|_| 0.0..8.0 / 255.0),
+        |x, y, pixel| {
+            let original_alpha = alpha.get_pixel(x, y)[0];
+            if original_alpha <= 0.0 || original_alpha >= 1.0 {
+                return (pixel, original_alpha.clamp(0.0, 1.0));
+            }
+            let new_alpha = posterize_normalized(pixel[0].clamp(0.0, 1.0), 3);
+            ([new_alpha; 3], new_alpha)
+        },
+    );
+    image::Rgb32FImage::from_fn(alpha.width(), alpha.height(), |x, y| {
+        image::Rgb([pixels[usize::try_from(y * alpha.width() + x).unwrap()]; 3])
+    })
+}
+
 const QUANTIZER_TRANSPARENT_COLOR: imagequant::RGBA = imagequant::RGBA::new(255, 255, 0, 1);
-const TRANSPARENT_COLOR_INDEX: u8 = 0;
+const MIN_PALETTE_COLOR_COUNT: usize = 2;
+
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+pub struct ReservedColor {
+    pub index: u8,
+    pub color: [u8; 3],
+}
+
+// Reserved palette entries keep their colors and are never used by quantized sprites
+#[derive(Clone, Debug, Default, serde::Deserialize, serde::Serialize)]
+pub struct PaletteLayout {
+    #[serde(default)]
+    pub transparent_color_index: u8,
+    #[serde(default)]
+    pub reserved_colors: Vec<ReservedColor>,
+}
+
+// Truecolor sprites quantized when compiling use the same settings as split sprites
+#[derive(Clone, Default)]
+pub struct SpriteQuantization {
+    pub dithering: Dithering,
+    pub color_space: color_space::ColorSpace,
+    pub palette_layout: PaletteLayout,
+}
+
+impl PaletteLayout {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for (i, reserved_color) in self.reserved_colors.iter().enumerate() {
+            anyhow::ensure!(
+                reserved_color.index != self.transparent_color_index,
+                "Reserved palette entry {} is the transparent color index",
+                reserved_color.index
+            );
+            anyhow::ensure!(
+                !self.reserved_colors[..i].iter().any(|x| x.index == reserved_color.index),
+                "Palette entry {} is reserved more than once",
+                reserved_color.index
+            );
+        }
+        anyhow::ensure!(
+            self.color_indices().len() >= MIN_PALETTE_COLOR_COUNT,
+            "At least {} palette entries must be left for colors",
+            MIN_PALETTE_COLOR_COUNT
+        );
+        Ok(())
+    }
+
+    fn reserved_color(&self, index: u8) -> Option<[u8; 3]> {
+        self.reserved_colors.iter().find(|x| x.index == index).map(|x| x.color)
+    }
+
+    // The entries that sprites are quantized to, in order
+    fn color_indices(&self) -> Vec<u8> {
+        (0..=u8::MAX)
+            .filter(|x| *x != self.transparent_color_index && self.reserved_color(*x).is_none())
+            .collect()
+    }
+}
 
 pub struct Histogram {
     quantizer: imagequant::Attributes,
     histogram: imagequant::Histogram,
-    colors: std::collections::HashMap<imagequant::RGBA, u32>,
+    colors: std::collections::BTreeMap<imagequant::RGBA, u32>,
+}
+
+fn new_quantizer_attributes() -> imagequant::Attributes {
+    let mut quantizer = imagequant::new();
+    quantizer.set_max_colors(u32::from(palt::PALT_COLOR_ENTRY_COUNT) - 1).unwrap();
+    quantizer
 }
 
 impl Histogram {
     pub fn new() -> Self {
-        let mut quantizer = imagequant::new();
-        quantizer.set_max_colors(u32::from(palt::PALT_COLOR_ENTRY_COUNT) - 1).unwrap();
+        let quantizer = new_quantizer_attributes();
         let histogram = imagequant::Histogram::new(&quantizer);
         Histogram {
             quantizer,
-            colors: std::collections::HashMap::new(),
+            colors: std::collections::BTreeMap::new(),
             histogram,
         }
     }
@@ -98,8 +339,15 @@ impl Histogram {
         }
     }
 
-    pub fn finalize(mut self) -> anyhow::Result<Quantizer> {
+    pub fn finalize(
+        mut self,
+        dithering: Dithering,
+        color_space: color_space::ColorSpace,
+        palette_layout: &PaletteLayout,
+    ) -> anyhow::Result<Quantizer> {
         anyhow::ensure!(!self.colors.is_empty(), "No colors added to histogram");
+        let color_indices = palette_layout.color_indices();
+        self.quantizer.set_max_colors(u32::try_from(color_indices.len()).unwrap()).unwrap();
 
         // The Sims 1 displays in 16-bit R5G6B5 color.
         // Palettes can contain any 24-bit color, but when rendered in-game they will be converted
@@ -126,13 +374,23 @@ impl Histogram {
         let mut quantization_result = self.histogram.quantize(&self.quantizer).unwrap();
 
         // Imagequants initial palette is converted to 16-bit R5G6B6 colors, ignoring duplicates.
-        let palette_set: std::collections::HashSet<[u8; 3]> = quantization_result
-            .palette()
-            .iter()
-            .map(|x| [posterize(x.r, 3), posterize(x.g, 2), posterize(x.b, 3)])
-            .collect();
+        // In perceptual color spaces colors that look the same as one already in the palette are also duplicates.
+        // The order of the colors is kept so that the same sprites always give the same palette.
         let palette = {
-            let mut palette: Vec<[u8; 3]> = palette_set.into_iter().collect();
+            let merge_distance = color_space.merge_distance();
+            let mut palette: Vec<[u8; 3]> = Vec::new();
+            let mut palette_coordinates: Vec<[f64; 3]> = Vec::new();
+            for color in quantization_result
+                .palette()
+                .iter()
+                .map(|x| [posterize(x.r, 3), posterize(x.g, 2), posterize(x.b, 3)])
+            {
+                let coordinates = color_space.coordinates(color);
+                if !palette_coordinates.iter().any(|x| color_space::distance(*x, coordinates) <= merge_distance) {
+                    palette.push(color);
+                    palette_coordinates.push(coordinates);
+                }
+            }
 
             // Imagequant can sometimes produce palettes which are a lot less than 255 colors
             // (pre posterization) even though there are more than 255 colors in the source images.
@@ -141,34 +399,82 @@ impl Histogram {
             // gets dropped unnecessarily.
             // A small amount of colors are also dropped when the palette is converted to r5g6b5 colors.
             // To workaround this, the palette is extended up to 255 colors with the next most used
-            // color that isn't already in the palette.
+            // color that isn't already in the palette. Perceptual color spaces first only use colors
+            // that don't look the same as any in the palette.
             let mut histogram_colors = histogram_colors.clone();
             histogram_colors.sort_by(|a, b| a.count.cmp(&b.count).reverse());
-            while palette.len() < std::cmp::min(usize::from(palt::PALT_COLOR_ENTRY_COUNT) - 1, histogram_colors.len()) {
+            let palette_size = std::cmp::min(color_indices.len(), histogram_colors.len());
+            for merge_distance in [merge_distance, 0.0] {
                 for entry in &histogram_colors {
-                    if !palette.iter().any(|x| x[0] == entry.color.r && x[1] == entry.color.g && x[2] == entry.color.b)
-                    {
-                        palette.push([entry.color.r, entry.color.g, entry.color.b]);
+                    if palette.len() >= palette_size {
                         break;
                     }
+                    let color = [entry.color.r, entry.color.g, entry.color.b];
+                    let coordinates = color_space.coordinates(color);
+                    if !palette_coordinates.iter().any(|x| color_space::distance(*x, coordinates) <= merge_distance) {
+                        palette.push(color);
+                        palette_coordinates.push(coordinates);
+                    }
                 }
             }
-            // Pad the palette out to 255 with unused default colors
-            while palette.len() < usize::from(palt::PALT_COLOR_ENTRY_COUNT) - 1 {
+            // Pad the palette out to 255 with unused default colors, less any reserved entries
+            while palette.len() < color_indices.len() {
                 palette.push([0, 0, 0]);
             }
             palette
         };
 
-        // The final palette is created with the transparent color at the beginning.
-        // All colors are added as fixed colors to an imagequant histogram, ready for it to quantize
-        // the final sprites.
+        // The colors fill the palette entries that are not transparent or reserved, in order
+        let mut palette = palette.into_iter();
+        let final_palette = (0..=u8::MAX)
+            .map(|i| {
+                if i == palette_layout.transparent_color_index {
+                    [255, 255, 0]
+                } else if let Some(reserved_color) = palette_layout.reserved_color(i) {
+                    reserved_color
+                } else {
+                    palette.next().unwrap()
+                }
+            })
+            .collect();
+        Ok(Quantizer::new(self.quantizer, final_palette, palette_layout, dithering))
+    }
+}
+
+pub struct Quantizer {
+    quantizer: imagequant::Attributes,
+    quantization_result: imagequant::QuantizationResult,
+    pub palette: Vec<[u8; 3]>,
+    pub transparent_color_index: u8,
+    // The palette entry of each imagequant palette index
+    palette_indices: Vec<u8>,
+    dithering: Dithering,
+}
+
+impl Quantizer {
+    fn new(
+        quantizer: imagequant::Attributes,
+        palette: Vec<[u8; 3]>,
+        palette_layout: &PaletteLayout,
+        dithering: Dithering,
+    ) -> Quantizer {
+        assert!(palette.len() == 256);
+        let color_indices = palette_layout.color_indices();
+        for color in color_indices.iter().map(|x| palette[usize::from(*x)]) {
+            assert!(color[0] == posterize(color[0], 3));
+            assert!(color[1] == posterize(color[1], 2));
+            assert!(color[2] == posterize(color[2], 3));
+        }
+
+        // The transparent color and then every color that is not reserved are added as fixed colors to an imagequant
+        // histogram, ready for it to quantize the final sprites. Imagequant's palette indices are mapped back to
+        // the palette entries afterwards.
         // The transparent color has an alpha of 1 so that imagequant will not match it to any
         // pixels that are not transparent.
 
-        let mut histogram = imagequant::Histogram::new(&self.quantizer);
+        let mut histogram = imagequant::Histogram::new(&quantizer);
         histogram.add_fixed_color(QUANTIZER_TRANSPARENT_COLOR, 0.0).unwrap();
-        for color in &palette {
+        for color in color_indices.iter().map(|x| palette[usize::from(*x)]) {
             histogram
                 .add_fixed_color(
                     imagequant::RGBA {
@@ -181,35 +487,43 @@ impl Histogram {
                 )
                 .unwrap();
         }
-        let quantization_result = histogram.quantize(&self.quantizer).unwrap();
-        let mut final_palette = vec![[255, 255, 0]];
-        final_palette.extend(&palette);
-
-        assert!(final_palette.len() == 256);
-        for color in &final_palette {
-            assert!(color[0] == posterize(color[0], 3));
-            assert!(color[1] == posterize(color[1], 2));
-            assert!(color[2] == posterize(color[2], 3));
+        let mut quantization_result = histogram.quantize(&quantizer).unwrap();
+        if dithering == Dithering::None {
+            quantization_result.set_dithering_level(0.0).unwrap();
         }
 
-        Ok(Quantizer {
-            quantizer: self.quantizer,
+        let mut palette_indices = vec![palette_layout.transparent_color_index];
+        palette_indices.extend(color_indices);
+        Quantizer {
+            quantizer,
             quantization_result,
-            palette: final_palette,
-            transparent_color_index: TRANSPARENT_COLOR_INDEX,
-        })
+            palette,
+            transparent_color_index: palette_layout.transparent_color_index,
+            palette_indices,
+            dithering,
+        }
     }
-}
 
-pub struct Quantizer {
-    quantizer: imagequant::Attributes,
-    quantization_result: imagequant::QuantizationResult,
-    pub palette: Vec<[u8; 3]>,
-    pub transparent_color_index: u8,
-}
+    // A fixed palette's colors are converted to R5G6B5 colors, as they are displayed in game, and its reserved entries
+    // are replaced by their reserved colors
+    pub fn from_palette(palette: &[[u8; 3]], palette_layout: &PaletteLayout, dithering: Dithering) -> Quantizer {
+        let palette = (0..=u8::MAX)
+            .zip(palette)
+            .map(|(i, x)| {
+                palette_layout
+                    .reserved_color(i)
+                    .unwrap_or([posterize(x[0], 3), posterize(x[1], 2), posterize(x[2], 3)])
+            })
+            .collect();
+        Quantizer::new(new_quantizer_attributes(), palette, palette_layout, dithering)
+    }
 
-impl Quantizer {
     pub fn quantize(&mut self, color: &R5g6b5Image, alpha: &image::Rgb32FImage) -> image::GrayImage {
+        // Imagequant remaps with Floyd-Steinberg dithering or none at all, other dithering is done here
+        if !matches!(self.dithering, Dithering::FloydSteinberg | Dithering::None) {
+            return self.remap(color, alpha);
+        }
+
         // For some reason imagequant produces noticeably bad results when quantizing the original
         // 24-bit image using the 256 R5G6B5 color palette.
         // The dithered R5G6B5 image is used instead. If the image and palette uses less than 255
@@ -241,7 +555,46 @@ impl Quantizer {
             .unwrap();
 
         let (_, quantized_pixels) = self.quantization_result.remapped(&mut quantizer_image).unwrap();
+        let quantized_pixels = quantized_pixels.iter().map(|x| self.palette_indices[usize::from(*x)]).collect();
 
         image::GrayImage::from_raw(color.0.width(), color.0.height(), quantized_pixels).unwrap()
     }
+
+    // Ordered dithering thresholds range over the average distance between neighbouring palette colors
+    fn remap(&self, color: &R5g6b5Image, alpha: &image::Rgb32FImage) -> image::GrayImage {
+        let palette: Vec<(u8, [f32; 3])> = self.palette_indices[1..]
+            .iter()
+            .map(|x| (*x, self.palette[usize::from(*x)].map(|x| f32::from(x) / 255.0)))
+            .collect();
+        let distance =
+            |a: &[f32; 3], b: &[f32; 3]| (a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2);
+
+        let neighbour_distances: Vec<f32> = palette
+            .iter()
+            .filter_map(|(_, a)| {
+                palette.iter().map(|(_, b)| distance(a, b)).filter(|x| *x > 0.0).min_by(|a, b| a.total_cmp(b))
+            })
+            .map(|x| x.sqrt())
+            .collect();
+        let spread = neighbour_distances.iter().sum::<f32>() / neighbour_distances.len().max(1) as f32;
+
+        let image = image::DynamicImage::ImageRgb8(color.0.clone()).into_rgb32f();
+        let pixels = dither(
+            &image,
+            self.dithering,
+            std::array::from_fn(|_| -spread / 2.0..spread / 2.0),
+            |x, y, pixel| {
+                // Transparent pixels keep their color, so they don't spread any error
+                if alpha.get_pixel(x, y)[0] <= 0.0 {
+                    return (pixel, self.transparent_color_index);
+                }
+                let (index, color) = palette
+                    .iter()
+                    .min_by(|(_, a), (_, b)| distance(a, &pixel).total_cmp(&distance(b, &pixel)))
+                    .unwrap();
+                (*color, *index)
+            },
+        );
+        image::GrayImage::from_raw(color.0.width(), color.0.height(), pixels).unwrap()
+    }
 }
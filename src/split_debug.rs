@@ -0,0 +1,84 @@
+use crate::error;
+
+use anyhow::Context;
+
+pub const ACCEPTED_COLOR: image::Rgba<u8> = image::Rgba([255, 255, 255, 255]);
+pub const LEFT_NEAR_PLANE_COLOR: image::Rgba<u8> = image::Rgba([255, 0, 0, 255]);
+pub const LEFT_FAR_PLANE_COLOR: image::Rgba<u8> = image::Rgba([255, 160, 0, 255]);
+pub const RIGHT_NEAR_PLANE_COLOR: image::Rgba<u8> = image::Rgba([0, 64, 255, 255]);
+pub const RIGHT_FAR_PLANE_COLOR: image::Rgba<u8> = image::Rgba([0, 224, 255, 255]);
+pub const OUT_OF_BOUNDS_COLOR: image::Rgba<u8> = image::Rgba([255, 0, 255, 255]);
+pub const DROPPED_COLOR: image::Rgba<u8> = image::Rgba([0, 0, 0, 255]);
+
+// The debug images are kept in memory until the split sprites are written, like the split sprites themselves
+pub struct SplitDebug {
+    pub directory: std::path::PathBuf,
+    images: Vec<(std::path::PathBuf, image::RgbaImage)>,
+}
+
+impl SplitDebug {
+    pub fn new(directory: std::path::PathBuf) -> SplitDebug {
+        SplitDebug {
+            directory,
+            images: Vec::new(),
+        }
+    }
+
+    pub fn insert_image(&mut self, file_path: std::path::PathBuf, image: image::RgbaImage) {
+        self.images.push((file_path, image));
+    }
+
+    pub fn extend(&mut self, split_debug: SplitDebug) {
+        self.images.extend(split_debug.images);
+    }
+
+    pub fn write(&self) -> anyhow::Result<()> {
+        if self.directory.is_dir() {
+            std::fs::remove_dir_all(&self.directory)
+                .with_context(|| format!("Failed to remove {}", self.directory.display()))?;
+        }
+
+        for (file_path, image) in &self.images {
+            if let Some(directory) = file_path.parent() {
+                std::fs::create_dir_all(directory)
+                    .with_context(|| format!("Failed to create directory {}", directory.display()))?;
+            }
+            image.save(file_path).with_context(|| error::file_write_error(file_path))?;
+        }
+
+        Ok(())
+    }
+}
+
+fn hsv_to_rgba(hue: f64, saturation: f64, value: f64) -> image::Rgba<u8> {
+    let sector = (hue.rem_euclid(1.0) * 6.0).floor();
+    let fraction = hue.rem_euclid(1.0) * 6.0 - sector;
+    let p = value * (1.0 - saturation);
+    let q = value * (1.0 - saturation * fraction);
+    let t = value * (1.0 - saturation * (1.0 - fraction));
+    let (r, g, b) = match sector as u8 {
+        0 => (value, t, p),
+        1 => (q, value, p),
+        2 => (p, value, t),
+        3 => (p, q, value),
+        4 => (t, p, value),
+        _ => (value, p, q),
+    };
+    image::Rgba([(r * 255.0) as u8, (g * 255.0) as u8, (b * 255.0) as u8, 255])
+}
+
+// Near depths are red and far depths blue, depths clamped to the tile's depth span are drawn darker
+pub fn depth_color(depth_normalized: f64) -> image::Rgba<u8> {
+    let value = if (0.0..=1.0).contains(&depth_normalized) {
+        1.0
+    } else {
+        0.5
+    };
+    hsv_to_rgba((1.0 - depth_normalized.clamp(0.0, 1.0)) * (2.0 / 3.0), 1.0, value)
+}
+
+// Neighbouring tiles get hues far apart from each other by stepping around the color wheel by the golden ratio
+pub fn tile_color(tile_index: i32) -> image::Rgba<u8> {
+    const GOLDEN_RATIO_CONJUGATE: f64 = 0.618033988749895;
+    hsv_to_rgba(f64::from(tile_index) * GOLDEN_RATIO_CONJUGATE + 0.15, 0.6, 0.95)
+}
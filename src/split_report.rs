@@ -0,0 +1,144 @@
+use crate::color_space;
+use crate::error;
+use crate::iff;
+use crate::sprite;
+
+use anyhow::Context;
+
+pub const DEFAULT_MAX_DELTA_E: f64 = 3.0;
+
+fn delta_e(a: [u8; 3], b: [u8; 3]) -> f64 {
+    color_space::distance(color_space::srgb_to_cielab(a), color_space::srgb_to_cielab(b))
+}
+
+#[derive(serde::Serialize)]
+struct SpriteReport {
+    frame: String,
+    rotation: String,
+    zoom_level: String,
+    // Identical sprites have an infinite PSNR, which is written as null
+    psnr: f64,
+    mean_delta_e: f64,
+    max_delta_e: f64,
+    over_threshold: bool,
+}
+
+#[derive(serde::Serialize)]
+pub struct PaletteReport {
+    palette_id: i16,
+    used_color_count: usize,
+    color_usage: Vec<u64>,
+    sprites: Vec<SpriteReport>,
+}
+
+impl PaletteReport {
+    pub fn new(palette_id: iff::IffChunkId, palette: &[[u8; 3]]) -> PaletteReport {
+        PaletteReport {
+            palette_id: palette_id.as_i16(),
+            used_color_count: 0,
+            color_usage: vec![0; palette.len()],
+            sprites: Vec::new(),
+        }
+    }
+
+    // Only the visible pixels are compared, sprites without any are left out
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_sprite(
+        &mut self,
+        frame_name: &str,
+        rotation: sprite::Rotation,
+        zoom_level: sprite::ZoomLevel,
+        original_color: &image::RgbImage,
+        alpha: &image::Rgb32FImage,
+        quantized_color: &image::GrayImage,
+        palette: &[[u8; 3]],
+    ) {
+        let mut squared_error_sum = 0.0;
+        let mut delta_e_sum = 0.0;
+        let mut max_delta_e: f64 = 0.0;
+        let mut pixel_count = 0u32;
+        for ((original, a), index) in original_color.pixels().zip(alpha.pixels()).zip(quantized_color.pixels()) {
+            if a[0] <= 0.0 {
+                continue;
+            }
+            let index = usize::from(index[0]);
+            self.color_usage[index] += 1;
+
+            let quantized = palette[index];
+            for (original, quantized) in original.0.iter().zip(quantized) {
+                squared_error_sum += (f64::from(*original) - f64::from(quantized)).powi(2);
+            }
+            let delta_e = delta_e(original.0, quantized);
+            delta_e_sum += delta_e;
+            max_delta_e = max_delta_e.max(delta_e);
+            pixel_count += 1;
+        }
+        if pixel_count == 0 {
+            return;
+        }
+
+        let mean_squared_error = squared_error_sum / (f64::from(pixel_count) * 3.0);
+        self.sprites.push(SpriteReport {
+            frame: frame_name.to_owned(),
+            rotation: rotation.to_string(),
+            zoom_level: zoom_level.to_string(),
+            psnr: 10.0 * (255.0f64.powi(2) / mean_squared_error).log10(),
+            mean_delta_e: delta_e_sum / f64::from(pixel_count),
+            max_delta_e,
+            over_threshold: false,
+        });
+    }
+}
+
+// The report is kept in memory until the split sprites are written, like the split sprites themselves
+#[derive(serde::Serialize)]
+pub struct SplitReport {
+    #[serde(skip)]
+    file_path: std::path::PathBuf,
+    max_mean_delta_e: f64,
+    palettes: Vec<PaletteReport>,
+}
+
+impl SplitReport {
+    pub fn new(file_path: std::path::PathBuf, max_mean_delta_e: f64) -> SplitReport {
+        SplitReport {
+            file_path,
+            max_mean_delta_e,
+            palettes: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, mut palette_report: PaletteReport) {
+        palette_report.used_color_count = palette_report.color_usage.iter().filter(|x| **x > 0).count();
+        for sprite_report in &mut palette_report.sprites {
+            sprite_report.over_threshold = sprite_report.mean_delta_e > self.max_mean_delta_e;
+        }
+        self.palettes.push(palette_report);
+    }
+
+    pub fn write(&self) -> anyhow::Result<()> {
+        let json_string = serde_json::to_string_pretty(self)
+            .with_context(|| format!("Failed to serialize json file {}", self.file_path.display()))?;
+        std::fs::write(&self.file_path, json_string).with_context(|| error::file_write_error(&self.file_path))?;
+
+        for palette_report in &self.palettes {
+            println!(
+                "Palette {}: {} of {} colors used",
+                palette_report.palette_id,
+                palette_report.used_color_count,
+                palette_report.color_usage.len()
+            );
+            for sprite_report in palette_report.sprites.iter().filter(|x| x.over_threshold) {
+                println!(
+                    "  Frame \"{}\" {} {} has a mean ΔE of {:.2}, over {:.2}",
+                    sprite_report.frame,
+                    sprite_report.zoom_level,
+                    sprite_report.rotation,
+                    sprite_report.mean_delta_e,
+                    self.max_mean_delta_e
+                );
+            }
+        }
+        Ok(())
+    }
+}
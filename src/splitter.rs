@@ -1,13 +1,23 @@
+use crate::build_cache;
+use crate::color_space;
+use crate::color_variant;
 use crate::error;
 use crate::iff;
+use crate::palette_file;
 use crate::quantizer;
+use crate::split_debug;
+use crate::split_report;
 use crate::sprite;
+use crate::sprite_image;
 
 use anyhow::Context;
 
 const MIN_OBJECT_DIMENSION: i32 = 1;
 const MAX_OBJECT_DIMENSION: i32 = 32;
 
+const DEPTH_BOUND_NEAR: f64 = 1.0;
+const DEPTH_BOUND_FAR: f64 = 10000.0;
+
 #[derive(Copy, Clone, serde::Deserialize, serde::Serialize)]
 struct ObjectDimensions {
     x: i32,
@@ -25,10 +35,178 @@ struct FrameDescription {
     sprite_id_reverse_y: bool,
 }
 
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+struct SharedPaletteObject {
+    object_name: String,
+    #[serde(default)]
+    variant: Option<String>,
+}
+
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+enum PaletteSource {
+    File(std::path::PathBuf),
+    SharedWith(Vec<SharedPaletteObject>),
+}
+
+// A palette ID can use a fixed palette from a palette file, or a palette made from its renders together with the
+// renders of the same palette ID in other objects or variants
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+struct PaletteDescription {
+    palette_id: iff::IffChunkId,
+    #[serde(flatten)]
+    source: PaletteSource,
+}
+
 #[derive(Clone, serde::Deserialize, serde::Serialize)]
 struct ObjectDescription {
     dimensions: ObjectDimensions,
     frames: Vec<FrameDescription>,
+    #[serde(default)]
+    dithering: quantizer::Dithering,
+    #[serde(default)]
+    alpha_dithering: Option<quantizer::Dithering>,
+    #[serde(default)]
+    downsampling: Downsampling,
+    #[serde(default)]
+    edges: EdgeTreatment,
+    #[serde(default)]
+    color_space: color_space::ColorSpace,
+    #[serde(flatten)]
+    palette_layout: quantizer::PaletteLayout,
+    #[serde(default)]
+    palettes: Vec<PaletteDescription>,
+    #[serde(default)]
+    color_variants: Vec<color_variant::ColorVariant>,
+}
+
+impl ObjectDescription {
+    fn color_variant(&self, variant: Option<&str>) -> Option<&color_variant::ColorVariant> {
+        variant.and_then(|variant| self.color_variants.iter().find(|x| x.name == variant))
+    }
+}
+
+fn read_object_description(source_directory: &std::path::Path, object_name: &str) -> anyhow::Result<ObjectDescription> {
+    let object_description_file_name = object_name.to_owned() + " - object description";
+    let object_description_file_path = source_directory.join(object_description_file_name).with_extension("json");
+    let json_string = std::fs::read_to_string(&object_description_file_path)
+        .with_context(|| error::file_read_error(&object_description_file_path))?;
+
+    serde_json::from_str::<ObjectDescription>(&json_string).with_context(|| {
+        format!(
+            "Failed to deserialize json file {}",
+            object_description_file_path.display()
+        )
+    })
+}
+
+fn variant_object_name(object_name: &str, variant: Option<&str>) -> String {
+    if let Some(variant) = variant {
+        format!("{} - {}", object_name, variant)
+    } else {
+        object_name.to_owned()
+    }
+}
+
+// Color variants are made from the renders of their object, other variants have their own renders
+fn full_sprites_directory(
+    source_directory: &std::path::Path,
+    object_name: &str,
+    variant: Option<&str>,
+    object_description: &ObjectDescription,
+) -> std::path::PathBuf {
+    let full_sprites_object_name = match object_description.color_variant(variant) {
+        Some(_) => object_name.to_owned(),
+        None => variant_object_name(object_name, variant),
+    };
+    source_directory.join(format!("{} - full sprites", full_sprites_object_name))
+}
+
+// Objects without an object description have no color variants
+pub fn color_variant_names(source_directory: &std::path::Path, object_name: &str) -> anyhow::Result<Vec<String>> {
+    let object_description_file_path =
+        source_directory.join(object_name.to_owned() + " - object description").with_extension("json");
+    if !object_description_file_path.is_file() {
+        return Ok(Vec::new());
+    }
+    let object_description = read_object_description(source_directory, object_name)?;
+    Ok(object_description.color_variants.iter().map(|x| x.name.clone()).collect())
+}
+
+// Objects without an object description quantize their truecolor sprites with the default settings
+pub fn sprite_quantization(
+    source_directory: &std::path::Path,
+    object_name: &str,
+) -> anyhow::Result<quantizer::SpriteQuantization> {
+    let object_description_file_path =
+        source_directory.join(object_name.to_owned() + " - object description").with_extension("json");
+    if !object_description_file_path.is_file() {
+        return Ok(quantizer::SpriteQuantization::default());
+    }
+    let object_description = read_object_description(source_directory, object_name)?;
+    object_description.palette_layout.validate()?;
+    Ok(quantizer::SpriteQuantization {
+        dithering: object_description.dithering,
+        color_space: object_description.color_space,
+        palette_layout: object_description.palette_layout,
+    })
+}
+
+// The renders of another object or variant sharing a palette
+struct SharedPaletteRenders {
+    variant_object_name: String,
+    full_sprites_directory: std::path::PathBuf,
+    object_description: ObjectDescription,
+    color_transforms: Vec<color_variant::ColorTransform>,
+}
+
+impl SharedPaletteRenders {
+    fn frame_descriptions(&self, palette_id: iff::IffChunkId) -> Vec<&FrameDescription> {
+        self.object_description.frames.iter().filter(|x| x.palette_id == palette_id).collect()
+    }
+}
+
+// Objects and variants are only read once, and the object being split is left out as its renders are always used
+fn read_shared_palette_renders(
+    source_directory: &std::path::Path,
+    object_name: &str,
+    shared_objects: &[SharedPaletteObject],
+    palette_id: iff::IffChunkId,
+) -> anyhow::Result<Vec<SharedPaletteRenders>> {
+    let mut shared_palette_renders: Vec<SharedPaletteRenders> = Vec::new();
+    for shared_object in shared_objects {
+        let shared_variant = shared_object.variant.as_deref();
+        let shared_object_name = variant_object_name(&shared_object.object_name, shared_variant);
+        if shared_object_name == object_name
+            || shared_palette_renders.iter().any(|x| x.variant_object_name == shared_object_name)
+        {
+            continue;
+        }
+
+        let object_description = read_object_description(source_directory, &shared_object.object_name)?;
+        let shared_palette_render = SharedPaletteRenders {
+            variant_object_name: shared_object_name,
+            full_sprites_directory: full_sprites_directory(
+                source_directory,
+                &shared_object.object_name,
+                shared_variant,
+                &object_description,
+            ),
+            color_transforms: object_description
+                .color_variant(shared_variant)
+                .map(|x| x.transforms.clone())
+                .unwrap_or_default(),
+            object_description,
+        };
+        anyhow::ensure!(
+            !shared_palette_render.frame_descriptions(palette_id).is_empty(),
+            "Failed to find any frames with palette ID {} in object {} to share the palette with",
+            palette_id.as_i16(),
+            shared_object.object_name
+        );
+        shared_palette_renders.push(shared_palette_render);
+    }
+    Ok(shared_palette_renders)
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -45,6 +223,8 @@ fn split_sprite(
     palette: &[[u8; 3]],
     palette_id: iff::IffChunkId,
     transparent_color_index: u8,
+    sprite_images: &mut sprite_image::SpriteImages,
+    mut split_debug: Option<&mut split_debug::SplitDebug>,
 ) -> anyhow::Result<()> {
     let extra_tiles = (object_dimensions.x - 1) + (object_dimensions.y - 1);
 
@@ -74,6 +254,10 @@ fn split_sprite(
     let mut full_sprite_p = full_sprite_p.clone();
     let mut full_sprite_a = full_sprite_a.clone();
 
+    let debug_file_name = |kind: &str| format!("{}_{}_{}.png", zoom_level, full_sprite_rotation_name, kind);
+    let mut debug_tiles =
+        split_debug.is_some().then(|| image::RgbaImage::new(full_sprite_a.width(), full_sprite_a.height()));
+
     for tile_y in 0..object_dimensions.y {
         for tile_x in 0..object_dimensions.x {
             let (x_offset, y_offset) = {
@@ -120,9 +304,6 @@ fn split_sprite(
             const TILE_DEPTH: f64 = TILE_DISTANCE_TO_CENTER / DISTANCE_TO_CENTER_FROM_CAMERA;
             const TILE_DEPTH_FULL_SPAN: f64 = 3.2; // why?
 
-            const DEPTH_BOUND_NEAR: f64 = 1.0;
-            const DEPTH_BOUND_FAR: f64 = 10000.0;
-
             let tile_depth_offset = -(y_offset as f64 / (tile_height as f64 / 2.0)) * TILE_DEPTH;
 
             let full_sprite_width = split_sprite_width + (extra_tiles * (tile_width / 2));
@@ -153,6 +334,11 @@ fn split_sprite(
             let mut split_sprite_z = image::GrayImage::new(split_sprite_width, split_sprite_height);
             let mut split_sprite_a = image::GrayImage::new(split_sprite_width, split_sprite_height);
 
+            let mut debug_depth =
+                split_debug.is_some().then(|| image::RgbaImage::new(split_sprite_width, split_sprite_height));
+            let mut debug_planes =
+                split_debug.is_some().then(|| image::RgbaImage::new(split_sprite_width, split_sprite_height));
+
             let (rotated_tile_x, rotated_tile_y) = match rotation {
                 sprite::Rotation::NorthWest => (tile_x, tile_y),
                 sprite::Rotation::NorthEast => (object_dimensions.y - 1 - tile_y, tile_x),
@@ -195,18 +381,36 @@ fn split_sprite(
                         DEPTH_BOUND_NEAR
                     };
 
-                    let depth = full_sprite_z.get_pixel(x, y)[0] as f64;
-                    let depth = {
-                        if !(DEPTH_BOUND_NEAR..DEPTH_BOUND_FAR).contains(&depth) {
-                            if let Some(ref full_sprite_z_extra) = full_sprite_z_extra {
-                                full_sprite_z_extra.get_pixel(x, y)[0] as f64
+                    let depth = pixel_depth(
+                        full_sprite_z.get_pixel(x, y)[0],
+                        full_sprite_z_extra.as_ref().map(|z| z.get_pixel(x, y)[0]),
+                    );
+                    let depth_normalized =
+                        (DISTANCE_TO_CENTER_FROM_CAMERA + tile_depth_offset + (TILE_DEPTH_FULL_SPAN / 2.0) - depth)
+                            / TILE_DEPTH_FULL_SPAN;
+
+                    if alpha > 0.0 {
+                        if let Some(ref mut debug_depth) = debug_depth {
+                            debug_depth.put_pixel(x, y, split_debug::depth_color(depth_normalized));
+                        }
+                        if let Some(ref mut debug_planes) = debug_planes {
+                            // Only the first plane a pixel is clipped by is shown
+                            let plane_color = if !(DEPTH_BOUND_NEAR..DEPTH_BOUND_FAR).contains(&depth) {
+                                split_debug::OUT_OF_BOUNDS_COLOR
+                            } else if depth < left_near_plane_depth {
+                                split_debug::LEFT_NEAR_PLANE_COLOR
+                            } else if depth > left_far_plane_depth {
+                                split_debug::LEFT_FAR_PLANE_COLOR
+                            } else if depth < right_near_plane_depth {
+                                split_debug::RIGHT_NEAR_PLANE_COLOR
+                            } else if depth > right_far_plane_depth {
+                                split_debug::RIGHT_FAR_PLANE_COLOR
                             } else {
-                                depth
-                            }
-                        } else {
-                            depth
+                                split_debug::ACCEPTED_COLOR
+                            };
+                            debug_planes.put_pixel(x, y, plane_color);
                         }
-                    };
+                    }
 
                     if alpha > 0.0
                         && depth >= left_near_plane_depth
@@ -218,20 +422,25 @@ fn split_sprite(
 
                         split_sprite_a.put_pixel(x, y, image::Luma([(alpha * 255.0) as u8]));
 
-                        let depth_normalized =
-                            (DISTANCE_TO_CENTER_FROM_CAMERA + tile_depth_offset + (TILE_DEPTH_FULL_SPAN / 2.0) - depth)
-                                / TILE_DEPTH_FULL_SPAN;
                         let depth_u8 = 255 - (depth_normalized.clamp(0.0, 1.0) * 255.0) as u8;
                         split_sprite_z.put_pixel(x, y, image::Luma([depth_u8]));
 
-                        full_sprite_p.put_pixel(x, y, image::Luma([0]));
+                        if let Some(ref mut debug_tiles) = debug_tiles {
+                            debug_tiles.put_pixel(
+                                sub_sprite_x + x,
+                                sub_sprite_y + y,
+                                split_debug::tile_color(tile_x + tile_y * object_dimensions.x),
+                            );
+                        }
+
+                        full_sprite_p.put_pixel(x, y, image::Luma([transparent_color_index]));
                         full_sprite_z.put_pixel(x, y, image::Rgb([1.0, 1.0, 1.0]));
                         if let Some(ref mut full_sprite_z_extra) = full_sprite_z_extra {
                             full_sprite_z_extra.put_pixel(x, y, image::Rgb([1.0, 1.0, 1.0]));
                         }
                         full_sprite_a.put_pixel(x, y, image::Rgb([0.0, 0.0, 0.0]));
                     } else {
-                        split_sprite_p.put_pixel(x, y, image::Luma([0]));
+                        split_sprite_p.put_pixel(x, y, image::Luma([transparent_color_index]));
                         split_sprite_z.put_pixel(x, y, image::Luma([255]));
                     }
                 }
@@ -239,10 +448,14 @@ fn split_sprite(
 
             let split_sprite_frame_directory = split_sprites_directory.join(format!("{frame_name} {tile_x}_{tile_y}"));
 
-            if !split_sprite_frame_directory.is_dir() {
-                std::fs::create_dir_all(&split_sprite_frame_directory).with_context(|| {
-                    format!("Failed to create directory {}", split_sprite_frame_directory.display())
-                })?;
+            if let Some(ref mut split_debug) = split_debug {
+                let debug_tile_directory = split_debug.directory.join(format!("{frame_name} {tile_x}_{tile_y}"));
+                if let Some(debug_depth) = debug_depth {
+                    split_debug.insert_image(debug_tile_directory.join(debug_file_name("depth")), debug_depth);
+                }
+                if let Some(debug_planes) = debug_planes {
+                    split_debug.insert_image(debug_tile_directory.join(debug_file_name("planes")), debug_planes);
+                }
             }
 
             let transmogrified_rotation = rotation.transmogrify();
@@ -266,49 +479,71 @@ fn split_sprite(
                 sprite::Channel::Alpha,
             );
 
-            {
-                let mut output_buffer = Vec::new();
-                let mut encoder = image::codecs::bmp::BmpEncoder::new(&mut output_buffer);
-                encoder
-                    .encode_with_palette(
-                        split_sprite_p.as_raw(),
-                        split_sprite_p.width(),
-                        split_sprite_p.height(),
-                        image::ExtendedColorType::L8,
-                        Some(palette),
-                    )
-                    .unwrap();
-
-                let mut file = std::fs::File::create(&split_sprite_p_file_path)
-                    .with_context(|| error::file_write_error(&split_sprite_p_file_path))?;
-                use std::io::Write;
-                file.write_all(&output_buffer).with_context(|| error::file_write_error(&split_sprite_p_file_path))?;
-            }
-            split_sprite_z
-                .save(&split_sprite_z_file_path)
-                .with_context(|| error::file_write_error(&split_sprite_z_file_path))?;
-            split_sprite_a
-                .save(&split_sprite_a_file_path)
-                .with_context(|| error::file_write_error(&split_sprite_a_file_path))?;
-
             let sprite_image_description = sprite::calculate_sprite_image_description(
                 &split_sprite_a,
                 zoom_level,
                 palette_id,
                 transparent_color_index,
             );
-            sprite::write_sprite_image_description_file(
-                &sprite_image_description,
-                &split_sprite_frame_directory,
-                zoom_level,
-                transmogrified_rotation,
-            )?;
+            sprite_images.insert_description(
+                sprite::sprite_description_file_path(
+                    &split_sprite_frame_directory,
+                    zoom_level,
+                    transmogrified_rotation,
+                ),
+                sprite_image_description,
+            );
+            sprite_images.insert_image(
+                split_sprite_p_file_path,
+                sprite_image::ChannelImage::Indexed {
+                    pixels: split_sprite_p,
+                    palette: palette.to_vec(),
+                },
+            );
+            sprite_images.insert_image(
+                split_sprite_z_file_path,
+                sprite_image::ChannelImage::Grayscale(split_sprite_z),
+            );
+            sprite_images.insert_image(
+                split_sprite_a_file_path,
+                sprite_image::ChannelImage::Grayscale(split_sprite_a),
+            );
         }
     }
 
+    if let (Some(split_debug), Some(mut debug_tiles)) = (split_debug, debug_tiles) {
+        // Pixels that are still visible in the full sprite were not sent to any tile
+        for (x, y, pixel) in full_sprite_a.enumerate_pixels() {
+            if quantizer::posterize_normalized(pixel[0], 3) == 0.0 {
+                continue;
+            }
+            let depth = pixel_depth(
+                full_sprite_z.get_pixel(x, y)[0],
+                full_sprite_z_extra.as_ref().map(|z| z.get_pixel(x, y)[0]),
+            );
+            let dropped_color = if (DEPTH_BOUND_NEAR..DEPTH_BOUND_FAR).contains(&depth) {
+                split_debug::DROPPED_COLOR
+            } else {
+                split_debug::OUT_OF_BOUNDS_COLOR
+            };
+            debug_tiles.put_pixel(x, y, dropped_color);
+        }
+        let debug_tiles_file_path = split_debug.directory.join(frame_name).join(debug_file_name("tiles"));
+        split_debug.insert_image(debug_tiles_file_path, debug_tiles);
+    }
+
     Ok(())
 }
 
+// Depths outside of the bounds are read from the extra depth render, if there is one
+fn pixel_depth(depth: f32, depth_extra: Option<f32>) -> f64 {
+    let depth = depth as f64;
+    match depth_extra {
+        Some(depth_extra) if !(DEPTH_BOUND_NEAR..DEPTH_BOUND_FAR).contains(&depth) => depth_extra as f64,
+        _ => depth,
+    }
+}
+
 struct DepthPlanes {
     left_far_large: image::Rgb32FImage,
     left_far_medium: image::Rgb32FImage,
@@ -373,7 +608,7 @@ impl DepthPlanes {
         }
     }
 
-    fn large(&self) -> DepthPlanesView {
+    fn large(&self) -> DepthPlanesView<'_> {
         DepthPlanesView {
             left_far: &self.left_far_large,
             left_near: &self.left_near_large,
@@ -382,7 +617,7 @@ impl DepthPlanes {
         }
     }
 
-    fn medium(&self) -> DepthPlanesView {
+    fn medium(&self) -> DepthPlanesView<'_> {
         DepthPlanesView {
             left_far: &self.left_far_medium,
             left_near: &self.left_near_medium,
@@ -391,7 +626,7 @@ impl DepthPlanes {
         }
     }
 
-    fn small(&self) -> DepthPlanesView {
+    fn small(&self) -> DepthPlanesView<'_> {
         DepthPlanesView {
             left_far: &self.left_far_small,
             left_near: &self.left_near_small,
@@ -436,24 +671,157 @@ fn lanczos_kernel(x: f32, a: f32) -> f32 {
     }
 }
 
-fn downsample_vertical(color: &image::RgbImage, alpha: &image::Rgb32FImage) -> (image::RgbImage, image::Rgb32FImage) {
+// Mitchell-Netravali cubic filters, Catmull-Rom is b = 0 and c = 0.5
+fn cubic_kernel(x: f32, b: f32, c: f32) -> f32 {
+    let x = x.abs();
+    if x < 1.0 {
+        ((12.0 - 9.0 * b - 6.0 * c) * x.powi(3) + (-18.0 + 12.0 * b + 6.0 * c) * x.powi(2) + (6.0 - 2.0 * b)) / 6.0
+    } else if x < 2.0 {
+        ((-b - 6.0 * c) * x.powi(3)
+            + (6.0 * b + 30.0 * c) * x.powi(2)
+            + (-12.0 * b - 48.0 * c) * x
+            + (8.0 * b + 24.0 * c))
+            / 6.0
+    } else {
+        0.0
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum DownsamplingFilter {
+    Box,
+    Triangle,
+    CatmullRom,
+    Mitchell,
+    #[default]
+    Lanczos,
+}
+
+fn default_downsampling_window() -> f32 {
+    3.0
+}
+
+// The window is the radius of the filter in pixels of the downsampled sprite. It is the number of lobes of the Lanczos
+// filter and cuts off the other filters when they are wider.
+#[derive(Copy, Clone, serde::Deserialize, serde::Serialize)]
+struct Downsampling {
+    #[serde(default)]
+    filter: DownsamplingFilter,
+    #[serde(default = "default_downsampling_window")]
+    window: f32,
+}
+
+impl Default for Downsampling {
+    fn default() -> Self {
+        Downsampling {
+            filter: DownsamplingFilter::default(),
+            window: default_downsampling_window(),
+        }
+    }
+}
+
+impl Downsampling {
+    fn support(self) -> f32 {
+        match self.filter {
+            DownsamplingFilter::Box => 0.5,
+            DownsamplingFilter::Triangle => 1.0,
+            DownsamplingFilter::CatmullRom | DownsamplingFilter::Mitchell => 2.0,
+            DownsamplingFilter::Lanczos => self.window,
+        }
+        .min(self.window)
+    }
+
+    fn kernel(self, x: f32) -> f32 {
+        if x.abs() >= self.support() {
+            return 0.0;
+        }
+        match self.filter {
+            DownsamplingFilter::Box => 1.0,
+            DownsamplingFilter::Triangle => 1.0 - x.abs(),
+            DownsamplingFilter::CatmullRom => cubic_kernel(x, 0.0, 0.5),
+            DownsamplingFilter::Mitchell => cubic_kernel(x, 1.0 / 3.0, 1.0 / 3.0),
+            DownsamplingFilter::Lanczos => lanczos_kernel(x, self.window),
+        }
+    }
+}
+
+// Renders can have their colors premultiplied by alpha, and edges with background colors bled into them. Opaque colors
+// are dilated into partially transparent edges one pixel at a time, replacing their colors.
+#[derive(Copy, Clone, Default, serde::Deserialize, serde::Serialize)]
+struct EdgeTreatment {
+    #[serde(default)]
+    premultiplied: bool,
+    #[serde(default)]
+    dilate: u32,
+}
+
+impl EdgeTreatment {
+    fn apply(self, color: &mut image::RgbImage, alpha: &image::Rgb32FImage) {
+        if self.premultiplied {
+            for (pixel, alpha_pixel) in color.pixels_mut().zip(alpha.pixels()) {
+                let alpha = alpha_pixel[0];
+                if alpha > 0.0 && alpha < 1.0 {
+                    pixel.0 = pixel.0.map(|x| linear_to_srgb((srgb_to_linear(x) / alpha).min(1.0)));
+                }
+            }
+        }
+
+        // Pixels are opaque when they are opaque in game
+        let width = color.width();
+        let mut opaque: Vec<bool> = alpha.pixels().map(|x| quantizer::posterize_normalized(x[0], 3) >= 1.0).collect();
+        for _ in 0..self.dilate {
+            let previous_color = color.clone();
+            let previous_opaque = opaque.clone();
+            for (x, y, pixel) in color.enumerate_pixels_mut() {
+                if previous_opaque[(y * width + x) as usize] {
+                    continue;
+                }
+
+                let mut neighbour_sum = [0.0, 0.0, 0.0];
+                let mut neighbour_count = 0;
+                for neighbour_y in y.saturating_sub(1)..=(y + 1).min(previous_color.height() - 1) {
+                    for neighbour_x in x.saturating_sub(1)..=(x + 1).min(width - 1) {
+                        if previous_opaque[(neighbour_y * width + neighbour_x) as usize] {
+                            let neighbour = previous_color.get_pixel(neighbour_x, neighbour_y);
+                            for (sum, channel) in neighbour_sum.iter_mut().zip(neighbour.0) {
+                                *sum += srgb_to_linear(channel);
+                            }
+                            neighbour_count += 1;
+                        }
+                    }
+                }
+                if neighbour_count > 0 {
+                    pixel.0 = neighbour_sum.map(|x| linear_to_srgb(x / neighbour_count as f32));
+                    opaque[(y * width + x) as usize] = true;
+                }
+            }
+        }
+    }
+}
+
+fn downsample_vertical(
+    color: &image::RgbImage,
+    alpha: &image::Rgb32FImage,
+    downsampling: Downsampling,
+) -> (image::RgbImage, image::Rgb32FImage) {
     let mut indices = Vec::with_capacity(16);
     let mut downsampled_color = image::RgbImage::new(color.width(), color.height() / 2);
     for y in 0..downsampled_color.height() {
         for x in 0..downsampled_color.width() {
             const RATIO: f32 = 2.0;
-            const WINDOW: f32 = 3.0;
+            let window = downsampling.support();
 
             let original_y = (y as f32 + 0.5) * RATIO;
 
-            let top_bound = (original_y - (WINDOW * RATIO)).floor().clamp(0.0, color.height() as f32) as u32;
-            let bottom_bound = (original_y + (WINDOW * RATIO)).ceil().clamp(0.0, color.height() as f32) as u32;
+            let top_bound = (original_y - (window * RATIO)).floor().clamp(0.0, color.height() as f32) as u32;
+            let bottom_bound = (original_y + (window * RATIO)).ceil().clamp(0.0, color.height() as f32) as u32;
 
             let mut contribution_sum = 0.0;
             for y in top_bound..bottom_bound {
                 if quantizer::posterize_normalized(alpha.get_pixel(x, y)[0], 3) > 0.0 {
                     let filter_position = (y as f32 - (original_y - 0.5)) / RATIO;
-                    let contribution = lanczos_kernel(filter_position, WINDOW);
+                    let contribution = downsampling.kernel(filter_position);
                     contribution_sum += contribution;
                     indices.push((x, y, contribution));
                 }
@@ -478,35 +846,31 @@ fn downsample_vertical(color: &image::RgbImage, alpha: &image::Rgb32FImage) -> (
             indices.clear();
         }
     }
-    (
-        downsampled_color,
-        image::imageops::resize(
-            alpha,
-            alpha.width(),
-            alpha.height() / 2,
-            image::imageops::FilterType::Lanczos3,
-        ),
-    )
+    (downsampled_color, downsample_alpha(alpha, downsampling, true))
 }
 
-fn downsample_horizontal(color: &image::RgbImage, alpha: &image::Rgb32FImage) -> (image::RgbImage, image::Rgb32FImage) {
+fn downsample_horizontal(
+    color: &image::RgbImage,
+    alpha: &image::Rgb32FImage,
+    downsampling: Downsampling,
+) -> (image::RgbImage, image::Rgb32FImage) {
     let mut indices = Vec::with_capacity(16);
     let mut downsampled_color = image::RgbImage::new(color.width() / 2, color.height());
     for y in 0..downsampled_color.height() {
         for x in 0..downsampled_color.width() {
             const RATIO: f32 = 2.0;
-            const WINDOW: f32 = 3.0;
+            let window = downsampling.support();
 
             let original_x = (x as f32 + 0.5) * RATIO;
 
-            let left_bound = (original_x - (WINDOW * RATIO)).floor().clamp(0.0, color.width() as f32) as u32;
-            let right_bound = (original_x + (WINDOW * RATIO)).ceil().clamp(0.0, color.width() as f32) as u32;
+            let left_bound = (original_x - (window * RATIO)).floor().clamp(0.0, color.width() as f32) as u32;
+            let right_bound = (original_x + (window * RATIO)).ceil().clamp(0.0, color.width() as f32) as u32;
 
             let mut contribution_sum = 0.0;
             for x in left_bound..right_bound {
                 if quantizer::posterize_normalized(alpha.get_pixel(x, y)[0], 3) > 0.0 {
                     let filter_position = (x as f32 - (original_x - 0.5)) / RATIO;
-                    let contribution = lanczos_kernel(filter_position, WINDOW);
+                    let contribution = downsampling.kernel(filter_position);
                     contribution_sum += contribution;
                     indices.push((x, y, contribution));
                 }
@@ -531,36 +895,241 @@ fn downsample_horizontal(color: &image::RgbImage, alpha: &image::Rgb32FImage) ->
             indices.clear();
         }
     }
-    (
-        downsampled_color,
-        image::imageops::resize(
-            alpha,
-            alpha.width() / 2,
-            alpha.height(),
-            image::imageops::FilterType::Lanczos3,
-        ),
-    )
+    (downsampled_color, downsample_alpha(alpha, downsampling, false))
 }
 
-fn downsample_sprites(color: &image::RgbImage, alpha: &image::Rgb32FImage) -> (image::RgbImage, image::Rgb32FImage) {
-    let (color, alpha) = downsample_vertical(color, alpha);
-    downsample_horizontal(&color, &alpha)
+// Every pixel contributes to the alpha, not only the visible ones, with the weights normalized before they are added up
+fn downsample_alpha(alpha: &image::Rgb32FImage, downsampling: Downsampling, vertical: bool) -> image::Rgb32FImage {
+    const RATIO: f32 = 2.0;
+    let window = downsampling.support();
+
+    let (width, height) = if vertical {
+        (alpha.width(), alpha.height() / 2)
+    } else {
+        (alpha.width() / 2, alpha.height())
+    };
+    let (length, downsampled_length, other_length) = if vertical {
+        (alpha.height(), height, width)
+    } else {
+        (alpha.width(), width, height)
+    };
+
+    let mut downsampled_alpha = image::Rgb32FImage::new(width, height);
+    let mut contributions = Vec::with_capacity(16);
+    for i in 0..downsampled_length {
+        let original_i = (i as f32 + 0.5) * RATIO;
+        let start_bound = (original_i - (window * RATIO)).floor().clamp(0.0, (length - 1) as f32) as u32;
+        let end_bound = ((original_i + (window * RATIO)).ceil() as u32).clamp(start_bound + 1, length);
+
+        contributions.clear();
+        contributions
+            .extend((start_bound..end_bound).map(|j| downsampling.kernel((j as f32 - (original_i - 0.5)) / RATIO)));
+        let contribution_sum: f32 = contributions.iter().sum();
+        for contribution in &mut contributions {
+            *contribution /= contribution_sum;
+        }
+
+        for j in 0..other_length {
+            let mut downsampled_pixel = [0.0, 0.0, 0.0];
+            for (k, contribution) in (start_bound..end_bound).zip(&contributions) {
+                let original_pixel = if vertical {
+                    alpha.get_pixel(j, k)
+                } else {
+                    alpha.get_pixel(k, j)
+                };
+                for (downsampled_channel, channel) in downsampled_pixel.iter_mut().zip(original_pixel.0) {
+                    *downsampled_channel += channel * contribution;
+                }
+            }
+            let downsampled_pixel = image::Rgb(downsampled_pixel.map(|x| x.clamp(0.0, 1.0)));
+            if vertical {
+                downsampled_alpha.put_pixel(j, i, downsampled_pixel);
+            } else {
+                downsampled_alpha.put_pixel(i, j, downsampled_pixel);
+            }
+        }
+    }
+    downsampled_alpha
 }
 
-pub fn split(source_directory: &std::path::Path, object_name: &str, variant: Option<&str>) -> anyhow::Result<()> {
-    let object_description = {
-        let object_description_file_name = object_name.to_owned() + " - object description";
-        let object_description_file_path = source_directory.join(object_description_file_name).with_extension("json");
-        let json_string = std::fs::read_to_string(&object_description_file_path)
-            .with_context(|| error::file_read_error(&object_description_file_path))?;
+fn downsample_sprites(
+    color: &image::RgbImage,
+    alpha: &image::Rgb32FImage,
+    downsampling: Downsampling,
+) -> (image::RgbImage, image::Rgb32FImage) {
+    let (color, alpha) = downsample_vertical(color, alpha, downsampling);
+    downsample_horizontal(&color, &alpha, downsampling)
+}
 
-        serde_json::from_str::<ObjectDescription>(&json_string).with_context(|| {
-            format!(
-                "Failed to deserialize json file {}",
-                object_description_file_path.display()
-            )
-        })?
-    };
+pub struct SplitTile {
+    pub directory: std::path::PathBuf,
+    pub sprite_id: iff::IffChunkId,
+    pub cached: bool,
+}
+
+// The split sprites are kept in memory, with the file paths they are written to, so that they can be compiled
+// without writing them first
+pub struct SplitSprites {
+    pub directory: std::path::PathBuf,
+    pub tiles: Vec<SplitTile>,
+    pub sprite_images: sprite_image::SpriteImages,
+    pub split_debug: Option<split_debug::SplitDebug>,
+    split_report: Option<split_report::SplitReport>,
+    cache_directory: Option<std::path::PathBuf>,
+    split_cache: build_cache::SplitCache,
+}
+
+impl SplitSprites {
+    pub fn write(&self) -> anyhow::Result<()> {
+        // Tiles that were not split again are kept from the last split, everything else is removed
+        if self.directory.is_dir() {
+            for entry in std::fs::read_dir(&self.directory).with_context(|| error::file_read_error(&self.directory))? {
+                let path = entry.with_context(|| error::file_read_error(&self.directory))?.path();
+                if self.tiles.iter().any(|x| x.cached && x.directory == path) {
+                    continue;
+                }
+                if path.is_dir() {
+                    std::fs::remove_dir_all(&path)
+                } else {
+                    std::fs::remove_file(&path)
+                }
+                .with_context(|| format!("Failed to remove {}", path.display()))?;
+            }
+        }
+
+        let rotations = [
+            sprite::Rotation::NorthWest,
+            sprite::Rotation::NorthEast,
+            sprite::Rotation::SouthEast,
+            sprite::Rotation::SouthWest,
+        ];
+        let zoom_levels = [sprite::ZoomLevel::Zero, sprite::ZoomLevel::One, sprite::ZoomLevel::Two];
+        for tile in self.tiles.iter().filter(|x| !x.cached) {
+            std::fs::create_dir_all(&tile.directory)
+                .with_context(|| format!("Failed to create directory {}", tile.directory.display()))?;
+
+            for zoom_level in zoom_levels {
+                for rotation in rotations {
+                    for channel in [sprite::Channel::Color, sprite::Channel::Depth, sprite::Channel::Alpha] {
+                        let file_path =
+                            sprite::sprite_channel_file_path(&tile.directory, zoom_level, rotation, channel);
+                        if let Some(channel_image) = self.sprite_images.image(&file_path) {
+                            write_split_sprite_channel(&file_path, channel_image)?;
+                        }
+                    }
+
+                    let description_file_path =
+                        sprite::sprite_description_file_path(&tile.directory, zoom_level, rotation);
+                    if let Some(sprite_image_description) = self.sprite_images.description(&description_file_path) {
+                        sprite::write_sprite_image_description_file(
+                            sprite_image_description,
+                            &tile.directory,
+                            zoom_level,
+                            rotation,
+                        )?;
+                    }
+                }
+            }
+
+            let tile_sprite_id_file_path = tile.directory.join("sprite id").with_extension("json");
+            let json_string = serde_json::to_string_pretty(&tile.sprite_id)
+                .with_context(|| format!("Failed to serialize json file {}", tile_sprite_id_file_path.display()))?;
+            std::fs::write(&tile_sprite_id_file_path, json_string)
+                .with_context(|| error::file_write_error(&tile_sprite_id_file_path))?;
+        }
+
+        if let Some(ref split_debug) = self.split_debug {
+            split_debug.write()?;
+        }
+        if let Some(ref split_report) = self.split_report {
+            split_report.write()?;
+        }
+
+        if let Some(ref cache_directory) = self.cache_directory {
+            self.split_cache.write(cache_directory)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn write_split_sprite_channel(
+    file_path: &std::path::Path,
+    channel_image: &sprite_image::ChannelImage,
+) -> anyhow::Result<()> {
+    match channel_image {
+        sprite_image::ChannelImage::Indexed { pixels, palette } => {
+            let mut output_buffer = Vec::new();
+            let mut encoder = image::codecs::bmp::BmpEncoder::new(&mut output_buffer);
+            encoder
+                .encode_with_palette(
+                    pixels.as_raw(),
+                    pixels.width(),
+                    pixels.height(),
+                    image::ExtendedColorType::L8,
+                    Some(palette),
+                )
+                .unwrap();
+
+            let mut file = std::fs::File::create(file_path).with_context(|| error::file_write_error(file_path))?;
+            use std::io::Write;
+            file.write_all(&output_buffer).with_context(|| error::file_write_error(file_path))
+        }
+        sprite_image::ChannelImage::Grayscale(pixels) => {
+            pixels.save(file_path).with_context(|| error::file_write_error(file_path))
+        }
+        sprite_image::ChannelImage::Truecolor(pixels) => {
+            pixels.save(file_path).with_context(|| error::file_write_error(file_path))
+        }
+    }
+}
+
+pub fn split(
+    source_directory: &std::path::Path,
+    object_name: &str,
+    variant: Option<&str>,
+    debug: bool,
+    report_max_mean_delta_e: Option<f64>,
+) -> anyhow::Result<()> {
+    // Frames from the cache have no debug images or report, so everything is split again for them
+    let cache = !debug && report_max_mean_delta_e.is_none();
+    split_sprites(
+        source_directory,
+        object_name,
+        variant,
+        debug,
+        report_max_mean_delta_e,
+        cache,
+    )?
+    .write()?;
+
+    // Color variants are made from the same renders, so they are split together with their object
+    if variant.is_none() {
+        for color_variant in color_variant_names(source_directory, object_name)? {
+            println!("Splitting color variant {}", color_variant);
+            split_sprites(
+                source_directory,
+                object_name,
+                Some(&color_variant),
+                debug,
+                report_max_mean_delta_e,
+                cache,
+            )?
+            .write()?;
+        }
+    }
+    Ok(())
+}
+
+pub fn split_sprites(
+    source_directory: &std::path::Path,
+    object_name: &str,
+    variant: Option<&str>,
+    debug: bool,
+    report_max_mean_delta_e: Option<f64>,
+    cache: bool,
+) -> anyhow::Result<SplitSprites> {
+    let object_description = read_object_description(source_directory, object_name)?;
 
     anyhow::ensure!(
         object_description.dimensions.x >= MIN_OBJECT_DIMENSION,
@@ -583,6 +1152,20 @@ pub fn split(source_directory: &std::path::Path, object_name: &str, variant: Opt
         !object_description.frames.is_empty(),
         "Failed to find any frames in object description"
     );
+    object_description.palette_layout.validate()?;
+    anyhow::ensure!(
+        object_description.downsampling.window > 0.0,
+        "The downsampling window must be over 0"
+    );
+
+    for (i, color_variant) in object_description.color_variants.iter().enumerate() {
+        anyhow::ensure!(!color_variant.name.is_empty(), "Color variant names must not be empty");
+        anyhow::ensure!(
+            !object_description.color_variants[..i].iter().any(|x| x.name == color_variant.name),
+            "Color variant {} is described more than once",
+            color_variant.name
+        );
+    }
 
     let base_sprite_ids: std::collections::HashSet<_> = object_description.frames.iter().map(|x| x.sprite_id).collect();
     anyhow::ensure!(
@@ -604,201 +1187,535 @@ pub fn split(source_directory: &std::path::Path, object_name: &str, variant: Opt
 
     let depth_planes = DepthPlanes::new();
 
-    let mut frame_palette_map = std::collections::HashMap::new();
+    // Palettes are split in the order they first appear in the object description
+    let mut frame_palette_map: Vec<(iff::IffChunkId, Vec<&FrameDescription>)> = Vec::new();
     for frame_description in &object_description.frames {
-        frame_palette_map
-            .entry(frame_description.palette_id)
-            .or_insert_with(Vec::new)
-            .push(frame_description);
+        match frame_palette_map.iter_mut().find(|x| x.0 == frame_description.palette_id) {
+            Some((_, frame_descriptions)) => frame_descriptions.push(frame_description),
+            None => frame_palette_map.push((frame_description.palette_id, vec![frame_description])),
+        }
     }
 
-    for frame_descriptions in frame_palette_map.values() {
+    let full_sprites_directory = full_sprites_directory(source_directory, object_name, variant, &object_description);
+    let color_transforms = match object_description.color_variant(variant) {
+        Some(color_variant) => color_variant.transforms.as_slice(),
+        None => &[],
+    };
+    let object_name = variant_object_name(object_name, variant);
+    let mut split_sprites = SplitSprites {
+        directory: source_directory.join(format!("{} - sprites", object_name)),
+        tiles: Vec::new(),
+        sprite_images: sprite_image::SpriteImages::default(),
+        split_debug: debug
+            .then(|| split_debug::SplitDebug::new(source_directory.join(format!("{} - split debug", object_name)))),
+        split_report: report_max_mean_delta_e.map(|x| {
+            let split_report_file_path =
+                source_directory.join(format!("{} - split report", object_name)).with_extension("json");
+            split_report::SplitReport::new(split_report_file_path, x)
+        }),
+        cache_directory: cache.then(|| build_cache::cache_directory(source_directory, &object_name)),
+        split_cache: build_cache::SplitCache::default(),
+    };
+    let previous_split_cache = match split_sprites.cache_directory {
+        Some(ref cache_directory) => build_cache::SplitCache::read(cache_directory),
+        None => build_cache::SplitCache::default(),
+    };
+
+    for (palette_id, frame_descriptions) in &frame_palette_map {
         split_palette(
             source_directory,
-            object_name,
-            variant,
-            object_description.dimensions,
+            &object_name,
+            &full_sprites_directory,
+            color_transforms,
+            &object_description,
             frame_descriptions,
-            object_description.frames[0].palette_id,
+            *palette_id,
             &depth_planes,
+            previous_split_cache.palette(*palette_id),
+            &mut split_sprites,
         )?;
     }
 
-    Ok(())
+    Ok(split_sprites)
 }
 
-fn split_palette(
-    source_directory: &std::path::Path,
-    object_name: &str,
-    variant: Option<&str>,
-    object_dimensions: ObjectDimensions,
-    frame_descriptions: &[&FrameDescription],
-    palette_id: iff::IffChunkId,
-    depth_planes: &DepthPlanes,
-) -> anyhow::Result<()> {
-    let object_name = if let Some(variant) = variant {
-        format!("{} - {}", object_name, variant)
-    } else {
-        object_name.to_owned()
-    };
-    let full_sprites_directory = source_directory.join(format!("{} - full sprites", object_name));
-    let split_sprites_directory = source_directory.join(format!("{} - sprites", object_name));
-    if split_sprites_directory.is_dir() {
-        std::fs::remove_dir_all(&split_sprites_directory)
-            .with_context(|| format!("Failed to remove {}", split_sprites_directory.display()))?;
+struct FullSprite<'a> {
+    frame_name: &'a str,
+    rotation: sprite::Rotation,
+    zoom_levels: Vec<FullSpriteZoomLevel>,
+}
+
+struct FullSpriteZoomLevel {
+    zoom_level: sprite::ZoomLevel,
+    color: image::RgbImage,
+    alpha: image::Rgb32FImage,
+    dithered_color: quantizer::R5g6b5Image,
+}
+
+// Renders are named after their rotation, and also after their zoom level when rendered at its resolution
+fn read_full_sprite_render(
+    full_sprite_frame_directory: &std::path::Path,
+    render_name: &str,
+    color_transforms: &[color_variant::ColorTransform],
+    edges: EdgeTreatment,
+) -> anyhow::Result<Option<(image::RgbImage, image::Rgb32FImage)>> {
+    let color_sprite_file_path = full_sprite_frame_directory.join(format!("{}_color.png", render_name));
+    if !color_sprite_file_path.is_file() {
+        return Ok(None);
     }
+    let mut color_sprite = image::open(&color_sprite_file_path)
+        .with_context(|| error::file_read_error(&color_sprite_file_path))?
+        .to_rgb8();
 
-    let mut sprites = Vec::new();
+    let alpha_sprite_file_path = full_sprite_frame_directory.join(format!("{}_alpha.exr", render_name));
+    let alpha_sprite = image::open(&alpha_sprite_file_path)
+        .with_context(|| error::file_read_error(&alpha_sprite_file_path))?
+        .to_rgb32f();
+    anyhow::ensure!(
+        alpha_sprite.dimensions() == color_sprite.dimensions(),
+        "{} is not the same size as {}",
+        alpha_sprite_file_path.display(),
+        color_sprite_file_path.display()
+    );
 
-    let mut histogram = quantizer::Histogram::new();
+    edges.apply(&mut color_sprite, &alpha_sprite);
+    color_variant::apply(
+        color_transforms,
+        &mut color_sprite,
+        full_sprite_frame_directory,
+        render_name,
+    )?;
+    Ok(Some((color_sprite, alpha_sprite)))
+}
 
-    for frame_description in frame_descriptions {
-        let sprite_count = sprites.len();
-        let rotations = [
-            sprite::Rotation::NorthWest,
-            sprite::Rotation::NorthEast,
-            sprite::Rotation::SouthEast,
-            sprite::Rotation::SouthWest,
-        ];
-        for rotation in rotations {
-            let full_sprite_frame_directory = full_sprites_directory.join(&frame_description.name);
+fn read_full_sprite<'a>(
+    full_sprites_directory: &std::path::Path,
+    color_transforms: &[color_variant::ColorTransform],
+    object_description: &ObjectDescription,
+    frame_description: &'a FrameDescription,
+    rotation: sprite::Rotation,
+) -> anyhow::Result<Option<FullSprite<'a>>> {
+    let full_sprite_frame_directory = full_sprites_directory.join(&frame_description.name);
+
+    let Some((mut color_sprite, mut alpha_sprite)) = read_full_sprite_render(
+        &full_sprite_frame_directory,
+        &rotation.to_string(),
+        color_transforms,
+        object_description.edges,
+    )?
+    else {
+        return Ok(None);
+    };
 
-            let color_sprite_file_name = rotation.to_string() + "_color.png";
-            let color_sprite_file_path = full_sprite_frame_directory.join(color_sprite_file_name);
-            if !color_sprite_file_path.is_file() {
-                continue;
+    // Each zoom level is downsampled from the one before it, unless it is rendered at its own resolution
+    let mut zoom_levels = Vec::new();
+    for zoom_level in [sprite::ZoomLevel::Zero, sprite::ZoomLevel::One, sprite::ZoomLevel::Two] {
+        let width = color_sprite.width() / 2;
+        let height = color_sprite.height() / 2;
+        let render_name = format!("{}_{}", zoom_level, rotation);
+        match read_full_sprite_render(
+            &full_sprite_frame_directory,
+            &render_name,
+            color_transforms,
+            object_description.edges,
+        )? {
+            Some(render) => {
+                anyhow::ensure!(
+                    render.0.dimensions() == (width, height),
+                    "The {} render of frame \"{}\" must be {}x{}",
+                    render_name,
+                    frame_description.name,
+                    width,
+                    height
+                );
+                (color_sprite, alpha_sprite) = render;
+            }
+            None => {
+                (color_sprite, alpha_sprite) =
+                    downsample_sprites(&color_sprite, &alpha_sprite, object_description.downsampling)
             }
-            let color_sprite = image::open(&color_sprite_file_path)
-                .with_context(|| error::file_read_error(&color_sprite_file_path))?
-                .to_rgb8();
+        }
+        zoom_levels.push(FullSpriteZoomLevel {
+            zoom_level,
+            color: color_sprite.clone(),
+            alpha: match object_description.alpha_dithering {
+                Some(alpha_dithering) => quantizer::dither_alpha_sprite(&alpha_sprite, alpha_dithering),
+                None => alpha_sprite.clone(),
+            },
+            dithered_color: quantizer::dither_color_sprite_to_r5g6b5(
+                color_sprite.clone(),
+                object_description.dithering,
+            ),
+        });
+    }
+
+    Ok(Some(FullSprite {
+        frame_name: &frame_description.name,
+        rotation,
+        zoom_levels,
+    }))
+}
 
-            let alpha_sprite_file_name = rotation.to_string() + "_alpha.exr";
-            let alpha_sprite_file_path = full_sprite_frame_directory.join(alpha_sprite_file_name);
-            let alpha_sprite = image::open(&alpha_sprite_file_path)
-                .with_context(|| error::file_read_error(&alpha_sprite_file_path))?
-                .to_rgb32f();
+// The sprites of every frame and rotation are read and downsampled to each zoom level in parallel, but kept in order so
+// that the palette and split sprites are the same no matter how many jobs are used
+fn read_full_sprites<'a>(
+    full_sprites_directory: &std::path::Path,
+    color_transforms: &[color_variant::ColorTransform],
+    object_description: &ObjectDescription,
+    frame_descriptions: &[&'a FrameDescription],
+) -> anyhow::Result<Vec<FullSprite<'a>>> {
+    use rayon::prelude::*;
 
-            let (color_sprite, alpha_sprite) = downsample_sprites(&color_sprite, &alpha_sprite);
+    let rotations = [
+        sprite::Rotation::NorthWest,
+        sprite::Rotation::NorthEast,
+        sprite::Rotation::SouthEast,
+        sprite::Rotation::SouthWest,
+    ];
 
-            let dithered_color_sprite = quantizer::dither_color_sprite_to_r5g6b5(color_sprite.clone());
+    let frame_sprites = frame_descriptions
+        .par_iter()
+        .map(|frame_description| {
+            let sprites = rotations
+                .par_iter()
+                .map(|rotation| {
+                    read_full_sprite(
+                        full_sprites_directory,
+                        color_transforms,
+                        object_description,
+                        frame_description,
+                        *rotation,
+                    )
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            let sprites: Vec<_> = sprites.into_iter().flatten().collect();
+            anyhow::ensure!(
+                !sprites.is_empty(),
+                "Failed to find any sprites to split in frame \"{}\"",
+                frame_description.name
+            );
+            Ok(sprites)
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    Ok(frame_sprites.into_iter().flatten().collect())
+}
 
-            histogram.add_colors(&dithered_color_sprite, &alpha_sprite);
+#[allow(clippy::too_many_arguments)]
+fn split_palette(
+    source_directory: &std::path::Path,
+    object_name: &str,
+    full_sprites_directory: &std::path::Path,
+    color_transforms: &[color_variant::ColorTransform],
+    object_description: &ObjectDescription,
+    frame_descriptions: &[&FrameDescription],
+    palette_id: iff::IffChunkId,
+    depth_planes: &DepthPlanes,
+    previous_palette_cache: Option<&build_cache::SplitCachePalette>,
+    split_sprites: &mut SplitSprites,
+) -> anyhow::Result<()> {
+    use rayon::prelude::*;
+
+    let object_dimensions = object_description.dimensions;
+    let dithering = object_description.dithering;
+    let palette_source = object_description.palettes.iter().find(|x| x.palette_id == palette_id).map(|x| &x.source);
+    let shared_palette_renders = match palette_source {
+        Some(PaletteSource::SharedWith(shared_objects)) => {
+            read_shared_palette_renders(source_directory, object_name, shared_objects, palette_id)?
+        }
+        _ => Vec::new(),
+    };
 
-            sprites.push((
-                &frame_description.name,
-                rotation,
-                color_sprite,
-                alpha_sprite,
-                dithered_color_sprite,
-            ));
+    let render_keys = frame_descriptions
+        .par_iter()
+        .map(|x| hash_frame_renders(full_sprites_directory, color_transforms, object_description, x))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let palette_layout_key =
+        serde_json::to_vec(&object_description.palette_layout).context("Failed to serialize palette layout")?;
+    let palette_key = {
+        let mut hasher = build_cache::ContentHasher::new();
+        hasher.update(palette_id.as_i16().to_le_bytes());
+        hasher.update(serde_json::to_vec(&object_description.color_space).context("Failed to serialize color space")?);
+        hasher.update(&palette_layout_key);
+        for render_key in &render_keys {
+            hasher.update(render_key);
+        }
+        if let Some(PaletteSource::File(palette_file_path)) = palette_source {
+            hasher.update_file(&source_directory.join(palette_file_path))?;
+        }
+        for shared_palette_render in &shared_palette_renders {
+            for frame_description in shared_palette_render.frame_descriptions(palette_id) {
+                hasher.update(hash_frame_renders(
+                    &shared_palette_render.full_sprites_directory,
+                    &shared_palette_render.color_transforms,
+                    &shared_palette_render.object_description,
+                    frame_description,
+                )?);
+            }
         }
+        hasher.finish()
+    };
 
-        anyhow::ensure!(
-            sprites.len() > sprite_count,
-            "Failed to find any sprites to split in frame \"{}\"",
-            frame_description.name
-        );
+    // Nothing sharing the palette has changed, so all of its tiles are kept from the last split
+    if let Some(previous_palette_cache) = previous_palette_cache
+        .filter(|x| x.key == palette_key && x.frames.iter().all(|x| cached_tiles_exist(&split_sprites.directory, x)))
+    {
+        for frame_cache in &previous_palette_cache.frames {
+            push_cached_tiles(split_sprites, frame_cache);
+        }
+        split_sprites.split_cache.palettes.push(previous_palette_cache.clone());
+        return Ok(());
     }
 
-    let mut quantizer = histogram
-        .finalize()
-        .with_context(|| format!("No sprites found in {}", full_sprites_directory.display()))?;
-
-    for (frame_name, rotation, color_sprite, alpha_sprite, dithered_color_sprite) in sprites {
-        split_sprite(
-            &full_sprites_directory,
-            &split_sprites_directory,
-            object_dimensions,
-            frame_name,
-            rotation,
-            sprite::ZoomLevel::Zero,
-            &quantizer.quantize(&dithered_color_sprite, &alpha_sprite),
-            &alpha_sprite,
-            &depth_planes.large(),
-            &quantizer.palette,
-            palette_id,
-            quantizer.transparent_color_index,
-        )?;
-
-        let (color_sprite, alpha_sprite) = downsample_sprites(&color_sprite, &alpha_sprite);
-        let dithered_color_sprite = quantizer::dither_color_sprite_to_r5g6b5(color_sprite.clone());
-
-        split_sprite(
-            &full_sprites_directory,
-            &split_sprites_directory,
-            object_dimensions,
-            frame_name,
-            rotation,
-            sprite::ZoomLevel::One,
-            &quantizer.quantize(&dithered_color_sprite, &alpha_sprite),
-            &alpha_sprite,
-            &depth_planes.medium(),
-            &quantizer.palette,
-            palette_id,
-            quantizer.transparent_color_index,
-        )?;
+    let sprites = read_full_sprites(
+        full_sprites_directory,
+        color_transforms,
+        object_description,
+        frame_descriptions,
+    )?;
+
+    let mut quantizer = match palette_source {
+        Some(PaletteSource::File(palette_file_path)) => {
+            let palette = palette_file::read(&source_directory.join(palette_file_path))?;
+            quantizer::Quantizer::from_palette(&palette, &object_description.palette_layout, dithering)
+        }
+        _ => {
+            let mut histogram = quantizer::Histogram::new();
+            for sprite in &sprites {
+                let zoom_level = &sprite.zoom_levels[0];
+                histogram.add_colors(&zoom_level.dithered_color, &zoom_level.alpha);
+            }
+            // Every object and variant sharing the palette adds the same renders, so they all get the same palette
+            for shared_palette_render in &shared_palette_renders {
+                let shared_sprites = read_full_sprites(
+                    &shared_palette_render.full_sprites_directory,
+                    &shared_palette_render.color_transforms,
+                    &shared_palette_render.object_description,
+                    &shared_palette_render.frame_descriptions(palette_id),
+                )?;
+                for sprite in &shared_sprites {
+                    let zoom_level = &sprite.zoom_levels[0];
+                    histogram.add_colors(&zoom_level.dithered_color, &zoom_level.alpha);
+                }
+            }
+            histogram
+                .finalize(
+                    dithering,
+                    object_description.color_space,
+                    &object_description.palette_layout,
+                )
+                .with_context(|| format!("No sprites found in {}", full_sprites_directory.display()))?
+        }
+    };
 
-        let (color_sprite, alpha_sprite) = downsample_sprites(&color_sprite, &alpha_sprite);
-        let dithered_color_sprite = quantizer::dither_color_sprite_to_r5g6b5(color_sprite.clone());
+    // Frames whose renders and palette are unchanged keep their tiles from the last split. Their renders are still
+    // read, as the palette is made from every frame sharing it.
+    let frame_keys: Vec<_> = render_keys
+        .iter()
+        .map(|render_key| {
+            let mut hasher = build_cache::ContentHasher::new();
+            hasher.update(render_key);
+            hasher.update(quantizer.palette.concat());
+            hasher.update(&palette_layout_key);
+            hasher.finish()
+        })
+        .collect();
+    let frame_caches: Vec<_> = frame_descriptions
+        .iter()
+        .zip(&frame_keys)
+        .map(|(frame_description, frame_key)| {
+            previous_palette_cache
+                .and_then(|x| x.frames.iter().find(|x| x.name == frame_description.name && x.key == *frame_key))
+                .filter(|x| cached_tiles_exist(&split_sprites.directory, x))
+        })
+        .collect();
+
+    let mut palette_report = split_sprites
+        .split_report
+        .as_ref()
+        .map(|_| split_report::PaletteReport::new(palette_id, &quantizer.palette));
+    let mut split_sprite_jobs = Vec::new();
+    for sprite in &sprites {
+        if frame_caches.iter().flatten().any(|x| x.name == sprite.frame_name) {
+            continue;
+        }
+        for zoom_level in &sprite.zoom_levels {
+            let quantized_color_sprite = quantizer.quantize(&zoom_level.dithered_color, &zoom_level.alpha);
+            if let Some(ref mut palette_report) = palette_report {
+                palette_report.add_sprite(
+                    sprite.frame_name,
+                    sprite.rotation,
+                    zoom_level.zoom_level,
+                    &zoom_level.color,
+                    &zoom_level.alpha,
+                    &quantized_color_sprite,
+                    &quantizer.palette,
+                );
+            }
+            split_sprite_jobs.push((sprite, zoom_level.zoom_level, quantized_color_sprite, &zoom_level.alpha));
+        }
+    }
+    if let (Some(split_report), Some(palette_report)) = (split_sprites.split_report.as_mut(), palette_report) {
+        split_report.push(palette_report);
+    }
 
-        split_sprite(
-            &full_sprites_directory,
-            &split_sprites_directory,
-            object_dimensions,
-            frame_name,
-            rotation,
-            sprite::ZoomLevel::Two,
-            &quantizer.quantize(&dithered_color_sprite, &alpha_sprite),
-            &alpha_sprite,
-            &depth_planes.small(),
-            &quantizer.palette,
-            palette_id,
-            quantizer.transparent_color_index,
-        )?;
+    // Tiles are split one after another within each sprite, as each tile takes its pixels out of the full sprite
+    let split_directory = split_sprites.directory.clone();
+    let debug_directory = split_sprites.split_debug.as_ref().map(|x| x.directory.clone());
+    let split_results = split_sprite_jobs
+        .par_iter()
+        .map(|(sprite, zoom_level, quantized_color_sprite, alpha_sprite)| {
+            let mut sprite_images = sprite_image::SpriteImages::default();
+            let mut split_debug = debug_directory.clone().map(split_debug::SplitDebug::new);
+            let depth_planes = match zoom_level {
+                sprite::ZoomLevel::Zero => depth_planes.large(),
+                sprite::ZoomLevel::One => depth_planes.medium(),
+                sprite::ZoomLevel::Two => depth_planes.small(),
+            };
+            split_sprite(
+                full_sprites_directory,
+                &split_directory,
+                object_dimensions,
+                sprite.frame_name,
+                sprite.rotation,
+                *zoom_level,
+                quantized_color_sprite,
+                alpha_sprite,
+                &depth_planes,
+                &quantizer.palette,
+                palette_id,
+                quantizer.transparent_color_index,
+                &mut sprite_images,
+                split_debug.as_mut(),
+            )?;
+            Ok((sprite_images, split_debug))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    for (sprite_images, split_debug) in split_results {
+        split_sprites.sprite_images.extend(sprite_images);
+        if let (Some(split_sprites_debug), Some(split_debug)) = (split_sprites.split_debug.as_mut(), split_debug) {
+            split_sprites_debug.extend(split_debug);
+        }
     }
 
-    for frame_description in frame_descriptions {
+    let mut palette_cache = build_cache::SplitCachePalette {
+        palette_id,
+        key: palette_key,
+        frames: Vec::new(),
+    };
+    for ((frame_description, frame_cache), frame_key) in frame_descriptions.iter().zip(frame_caches).zip(frame_keys) {
+        if let Some(frame_cache) = frame_cache {
+            push_cached_tiles(split_sprites, frame_cache);
+            palette_cache.frames.push(frame_cache.clone());
+            continue;
+        }
+
+        let mut frame_cache = build_cache::SplitCacheFrame {
+            name: frame_description.name.clone(),
+            key: frame_key,
+            tiles: Vec::new(),
+        };
         for y in 0..object_dimensions.y {
             for x in 0..object_dimensions.x {
                 let split_sprite_frame_directory =
-                    split_sprites_directory.join(format!("{} {x}_{y}", frame_description.name));
-                if !split_sprite_frame_directory.is_dir() {
+                    split_sprites.directory.join(format!("{} {x}_{y}", frame_description.name));
+                if is_tile_empty(&split_sprite_frame_directory, &split_sprites.sprite_images) {
                     continue;
                 }
-                if is_tile_empty(&split_sprite_frame_directory)? {
-                    std::fs::remove_dir_all(&split_sprite_frame_directory)
-                        .with_context(|| format!("Failed to remove {}", split_sprite_frame_directory.display()))?;
-                } else {
-                    let sprite_id = {
-                        let x = if frame_description.sprite_id_reverse_x {
-                            object_dimensions.x - x - 1
-                        } else {
-                            x
-                        };
-                        let y = if frame_description.sprite_id_reverse_y {
-                            object_dimensions.y - y - 1
-                        } else {
-                            y
-                        };
-                        frame_description.sprite_id + i16::try_from(x + (y * object_dimensions.x)).unwrap()
-                    };
 
-                    let tile_sprite_id_file_path =
-                        split_sprite_frame_directory.join("sprite id").with_extension("json");
-                    let json_string = serde_json::to_string_pretty(&sprite_id).with_context(|| {
-                        format!("Failed to serialize json file {}", tile_sprite_id_file_path.display())
-                    })?;
-                    std::fs::write(&tile_sprite_id_file_path, json_string)
-                        .with_context(|| error::file_write_error(&tile_sprite_id_file_path))?;
-                }
+                let sprite_id = {
+                    let x = if frame_description.sprite_id_reverse_x {
+                        object_dimensions.x - x - 1
+                    } else {
+                        x
+                    };
+                    let y = if frame_description.sprite_id_reverse_y {
+                        object_dimensions.y - y - 1
+                    } else {
+                        y
+                    };
+                    frame_description.sprite_id + i16::try_from(x + (y * object_dimensions.x)).unwrap()
+                };
+                frame_cache.tiles.push(build_cache::SplitCacheTile {
+                    directory_name: format!("{} {x}_{y}", frame_description.name),
+                    sprite_id,
+                });
+                split_sprites.tiles.push(SplitTile {
+                    directory: split_sprite_frame_directory,
+                    sprite_id,
+                    cached: false,
+                });
             }
         }
+        palette_cache.frames.push(frame_cache);
     }
+    split_sprites.split_cache.palettes.push(palette_cache);
 
     Ok(())
 }
 
-fn is_tile_empty(split_sprite_frame_tile_directory: &std::path::Path) -> anyhow::Result<bool> {
+// Renders are hashed together with the object dimensions and frame description, which decide how they are split
+fn hash_frame_renders(
+    full_sprites_directory: &std::path::Path,
+    color_transforms: &[color_variant::ColorTransform],
+    object_description: &ObjectDescription,
+    frame_description: &FrameDescription,
+) -> anyhow::Result<String> {
+    let mut hasher = build_cache::ContentHasher::new();
+    hasher.update(serde_json::to_vec(&object_description.dimensions).context("Failed to serialize object dimensions")?);
+    if !color_transforms.is_empty() {
+        hasher.update(serde_json::to_vec(color_transforms).context("Failed to serialize color transforms")?);
+    }
+    hasher.update(serde_json::to_vec(&object_description.dithering).context("Failed to serialize dithering")?);
+    hasher.update(
+        serde_json::to_vec(&object_description.alpha_dithering).context("Failed to serialize alpha dithering")?,
+    );
+    hasher.update(serde_json::to_vec(&object_description.downsampling).context("Failed to serialize downsampling")?);
+    hasher.update(serde_json::to_vec(&object_description.edges).context("Failed to serialize edge treatment")?);
+    hasher.update(serde_json::to_vec(frame_description).context("Failed to serialize frame description")?);
+
+    let full_sprite_frame_directory = full_sprites_directory.join(&frame_description.name);
+    if full_sprite_frame_directory.is_dir() {
+        let mut file_paths = Vec::new();
+        for entry in std::fs::read_dir(&full_sprite_frame_directory)
+            .with_context(|| error::file_read_error(&full_sprite_frame_directory))?
+        {
+            let path = entry.with_context(|| error::file_read_error(&full_sprite_frame_directory))?.path();
+            if path.is_file() {
+                file_paths.push(path);
+            }
+        }
+        file_paths.sort();
+        for file_path in file_paths {
+            hasher.update(file_path.file_name().unwrap_or_default().as_encoded_bytes());
+            hasher.update_file(&file_path)?;
+        }
+    }
+
+    Ok(hasher.finish())
+}
+
+fn cached_tiles_exist(split_sprites_directory: &std::path::Path, frame_cache: &build_cache::SplitCacheFrame) -> bool {
+    frame_cache
+        .tiles
+        .iter()
+        .all(|x| split_sprites_directory.join(&x.directory_name).join("sprite id").with_extension("json").is_file())
+}
+
+fn push_cached_tiles(split_sprites: &mut SplitSprites, frame_cache: &build_cache::SplitCacheFrame) {
+    for tile in &frame_cache.tiles {
+        split_sprites.tiles.push(SplitTile {
+            directory: split_sprites.directory.join(&tile.directory_name),
+            sprite_id: tile.sprite_id,
+            cached: true,
+        });
+    }
+}
+
+fn is_tile_empty(
+    split_sprite_frame_tile_directory: &std::path::Path,
+    sprite_images: &sprite_image::SpriteImages,
+) -> bool {
     let rotations = [
         sprite::Rotation::NorthWest,
         sprite::Rotation::NorthEast,
@@ -814,17 +1731,15 @@ fn is_tile_empty(split_sprite_frame_tile_directory: &std::path::Path) -> anyhow:
                 rotation,
                 sprite::Channel::Alpha,
             );
-            if !split_sprite_a_file_path.is_file() {
-                continue;
-            }
-            let split_sprite_a = image::open(&split_sprite_a_file_path)
-                .with_context(|| error::file_read_error(&split_sprite_a_file_path))?
-                .to_luma8();
-            if split_sprite_a.pixels().any(|x| x[0] != 0) {
-                return Ok(false);
+            if let Some(sprite_image::ChannelImage::Grayscale(split_sprite_a)) =
+                sprite_images.image(&split_sprite_a_file_path)
+            {
+                if split_sprite_a.pixels().any(|x| x[0] != 0) {
+                    return false;
+                }
             }
         }
     }
 
-    Ok(true)
+    true
 }
@@ -1,7 +1,8 @@
-use crate::error;
+use crate::build_cache;
+use crate::diagnostics;
 use crate::iff;
-use crate::iff_description;
 use crate::sprite;
+use crate::sprite_image;
 
 use anyhow::Context;
 use serde_with::serde_as;
@@ -154,18 +155,219 @@ impl SpriteFrame {
             .file_path_relative)
     }
 
-    pub fn sprite_channel_file_path_relative_mut(
-        &mut self,
+    pub fn sprite_channel_file_paths_relative_mut(&mut self) -> impl Iterator<Item = &mut String> {
+        self.sprite_channels.iter_mut().map(|x| &mut x.file_path_relative)
+    }
+
+    // Returns the file path of a channel, or None if the channel is not listed and has to be generated.
+    // Listed channels are always read, so that a missing file is an error instead of being generated.
+    pub fn listed_sprite_channel_file_path(
+        &self,
+        source_directory: &std::path::Path,
         channel_type: SpriteChannelType,
-        sprite_id: iff::IffChunkId,
-    ) -> anyhow::Result<&mut String> {
-        Ok(&mut self
-            .sprite_channels
-            .iter_mut()
+        generated: bool,
+    ) -> Option<std::path::PathBuf> {
+        if generated {
+            return None;
+        }
+        self.sprite_channels
+            .iter()
             .find(|x| x.channel_type == channel_type)
-            .with_context(|| sprite_channel_error(channel_type, sprite_id, self.index))?
-            .file_path_relative)
+            .map(|x| source_directory.join(&x.file_path_relative))
+    }
+
+    pub fn read_alpha_sprite(
+        &self,
+        source_directory: &std::path::Path,
+        sprite_id: iff::IffChunkId,
+        channel_generation: ChannelGeneration,
+        sprite_images: &sprite_image::SpriteImages,
+    ) -> anyhow::Result<image::GrayImage> {
+        if let Some(file_path) = self.listed_sprite_channel_file_path(
+            source_directory,
+            SpriteChannelType::Alpha,
+            channel_generation.alpha.is_some(),
+        ) {
+            return Ok(sprite_images.read_channel_image(&file_path)?.into_gray());
+        }
+
+        let file_path =
+            source_directory.join(self.sprite_channel_file_path_relative(SpriteChannelType::Color, sprite_id)?);
+        let channel_image = sprite_images.read_channel_image(&file_path)?;
+        let (width, height) = (channel_image.width(), channel_image.height());
+        let (pixels_p, transparent_color_index) = match channel_image {
+            sprite_image::ChannelImage::Indexed { pixels, .. } => (pixels.into_raw(), self.transparent_color_index),
+            sprite_image::ChannelImage::Truecolor(pixels) => {
+                // Truecolor sprites are transparent where their own alpha is
+                if channel_generation.alpha.is_none() {
+                    return Ok(image::GrayImage::from_fn(width, height, |x, y| {
+                        image::Luma([pixels.get_pixel(x, y)[3]])
+                    }));
+                }
+                (pixels.pixels().map(|x| u8::from(x[3] != 0)).collect(), 0)
+            }
+            sprite_image::ChannelImage::Grayscale(pixels) => (vec![1; pixels.len()], 0),
+        };
+        let pixels_a = generate_alpha_channel(
+            &pixels_p,
+            usize::try_from(width).unwrap(),
+            transparent_color_index,
+            channel_generation.alpha.unwrap_or(AlphaGeneration::Hard),
+        );
+        Ok(image::GrayImage::from_raw(width, height, pixels_a).unwrap())
+    }
+
+    // Reads the channels of the frame cropped to its bounds, generating the missing ones
+    pub fn read_channels(
+        &self,
+        source_directory: &std::path::Path,
+        sprite_id: iff::IffChunkId,
+        channel_generation: ChannelGeneration,
+        sprite_images: &sprite_image::SpriteImages,
+    ) -> anyhow::Result<FrameChannels> {
+        let width = u32::try_from(self.cropped_width).unwrap();
+        let height = u32::try_from(self.cropped_height).unwrap();
+        let file_path_p =
+            source_directory.join(self.sprite_channel_file_path_relative(SpriteChannelType::Color, sprite_id)?);
+        let file_path_z = self.listed_sprite_channel_file_path(
+            source_directory,
+            SpriteChannelType::Depth,
+            channel_generation.depth.is_some(),
+        );
+        let file_path_a = self.listed_sprite_channel_file_path(
+            source_directory,
+            SpriteChannelType::Alpha,
+            channel_generation.alpha.is_some(),
+        );
+
+        let x = u32::try_from(self.bounds_left).unwrap();
+        let y = u32::try_from(self.bounds_top).unwrap();
+        let crop = |image: &image::GrayImage, file_path: &std::path::Path| {
+            sprite_image::crop(
+                image,
+                file_path,
+                x,
+                y,
+                std::cmp::max(width, 1),
+                std::cmp::max(height, 1),
+            )
+        };
+
+        let (pixels_p, own_pixels_a, transparent_color_index) = match sprite_images.quantized(&file_path_p) {
+            Some(quantized_sprite) => (
+                crop(&quantized_sprite.pixels, &file_path_p)?,
+                Some(crop(&quantized_sprite.alpha, &file_path_p)?),
+                quantized_sprite.transparent_color_index,
+            ),
+            None => {
+                let pixels = sprite_images.read_channel_image(&file_path_p)?.into_indexed(&file_path_p)?;
+                (crop(&pixels, &file_path_p)?, None, self.transparent_color_index)
+            }
+        };
+        let pixels_z = match file_path_z {
+            Some(file_path_z) => crop(
+                &sprite_images.read_channel_image(&file_path_z)?.into_gray(),
+                &file_path_z,
+            )?,
+            None => generate_depth_channel(&pixels_p, channel_generation.depth.unwrap_or(DepthGeneration::Flat)),
+        };
+        // Truecolor color channels bring their own alpha, which has already been replaced by the
+        // alpha channel file when they were quantized
+        let pixels_a = match (file_path_a, own_pixels_a) {
+            (_, Some(own_pixels_a)) if channel_generation.alpha.is_none() => own_pixels_a,
+            (Some(file_path_a), _) => crop(
+                &sprite_images.read_channel_image(&file_path_a)?.into_gray(),
+                &file_path_a,
+            )?,
+            _ => generate_alpha_channel(
+                &pixels_p,
+                usize::try_from(std::cmp::max(width, 1)).unwrap(),
+                transparent_color_index,
+                channel_generation.alpha.unwrap_or(AlphaGeneration::Hard),
+            ),
+        };
+
+        Ok(FrameChannels {
+            color: pixels_p,
+            depth: pixels_z,
+            alpha: pixels_a,
+            transparent_color_index,
+        })
+    }
+}
+
+pub struct FrameChannels {
+    pub color: Vec<u8>,
+    pub depth: Vec<u8>,
+    pub alpha: Vec<u8>,
+    pub transparent_color_index: u8,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum DepthGeneration {
+    Flat,
+    Far,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum AlphaGeneration {
+    Hard,
+    Soft,
+}
+
+// Channels to generate from the color channel even if their files exist. Missing depth and alpha channels
+// are always generated, as flat depth and hard alpha unless specified otherwise.
+#[derive(Copy, Clone, Default, Debug)]
+pub struct ChannelGeneration {
+    pub depth: Option<DepthGeneration>,
+    pub alpha: Option<AlphaGeneration>,
+}
+
+fn generate_depth_channel(pixels_p: &[u8], depth_generation: DepthGeneration) -> Vec<u8> {
+    // Flat depth places every pixel halfway into the tile, far depth behind everything else
+    const FLAT_DEPTH: u8 = 128;
+    const FAR_DEPTH: u8 = 255;
+    let depth = match depth_generation {
+        DepthGeneration::Flat => FLAT_DEPTH,
+        DepthGeneration::Far => FAR_DEPTH,
+    };
+    vec![depth; pixels_p.len()]
+}
+
+fn generate_alpha_channel(
+    pixels_p: &[u8],
+    width: usize,
+    transparent_color_index: u8,
+    alpha_generation: AlphaGeneration,
+) -> Vec<u8> {
+    let is_opaque = |x: usize, y: usize| pixels_p[y * width + x] != transparent_color_index;
+    let height = pixels_p.len().checked_div(width).unwrap_or(0);
+
+    let mut pixels_a = vec![0u8; pixels_p.len()];
+    for y in 0..height {
+        for x in 0..width {
+            if !is_opaque(x, y) {
+                continue;
+            }
+            pixels_a[y * width + x] = match alpha_generation {
+                AlphaGeneration::Hard => 255,
+                AlphaGeneration::Soft => {
+                    // Soften the edges inwards by the share of opaque pixels around each pixel,
+                    // so no transparent color ends up being drawn
+                    let mut opaque_count = 0;
+                    for neighbour_y in y.saturating_sub(1)..=y + 1 {
+                        for neighbour_x in x.saturating_sub(1)..=x + 1 {
+                            if neighbour_x < width && neighbour_y < height && is_opaque(neighbour_x, neighbour_y) {
+                                opaque_count += 1;
+                            }
+                        }
+                    }
+                    u8::try_from(255 * opaque_count / 9).unwrap()
+                }
+            };
+        }
     }
+    pixels_a
 }
 
 fn sprite_channel_error(
@@ -213,14 +415,54 @@ struct SpriteChannel {
 }
 
 impl Sprite {
-    pub fn to_chunk(&self, source_directory: &std::path::Path) -> anyhow::Result<iff::IffChunk> {
+    // Hashes everything the chunk is encoded from, the sprite description, channel generation, channel images
+    // and the quantized palette of truecolor sprites
+    pub fn content_hash(
+        &self,
+        source_directory: &std::path::Path,
+        channel_generation: ChannelGeneration,
+        sprite_images: &sprite_image::SpriteImages,
+    ) -> anyhow::Result<String> {
+        let mut hasher = build_cache::ContentHasher::new();
+        hasher.update(serde_json::to_vec(self).context("Failed to serialize sprite")?);
+        hasher.update(format!("{:?}", channel_generation));
+        if let Some(palette) = sprite_images.quantized_palette(self.palette_chunk_id) {
+            hasher.update(palette.concat());
+        }
+        for frame in &self.sprite_frames {
+            let file_path_p = source_directory
+                .join(frame.sprite_channel_file_path_relative(SpriteChannelType::Color, self.chunk_id)?);
+            sprite_images.hash_channel(&file_path_p, &mut hasher)?;
+            for (channel_type, generated) in [
+                (SpriteChannelType::Depth, channel_generation.depth.is_some()),
+                (SpriteChannelType::Alpha, channel_generation.alpha.is_some()),
+            ] {
+                match frame.listed_sprite_channel_file_path(source_directory, channel_type, generated) {
+                    Some(file_path) => sprite_images.hash_channel(&file_path, &mut hasher)?,
+                    None => hasher.update([]),
+                }
+            }
+        }
+        Ok(hasher.finish())
+    }
+
+    pub fn to_chunk(
+        &self,
+        source_directory: &std::path::Path,
+        channel_generation: ChannelGeneration,
+        sprite_images: &sprite_image::SpriteImages,
+    ) -> anyhow::Result<iff::IffChunk> {
         match self.sprite_type {
-            SpriteType::Spr1 => self.to_spr1_chunk(source_directory),
-            SpriteType::Spr2 => self.to_spr2_chunk(source_directory),
+            SpriteType::Spr1 => self.to_spr1_chunk(source_directory, sprite_images),
+            SpriteType::Spr2 => self.to_spr2_chunk(source_directory, channel_generation, sprite_images),
         }
     }
 
-    fn to_spr1_chunk(&self, source_directory: &std::path::Path) -> anyhow::Result<iff::IffChunk> {
+    fn to_spr1_chunk(
+        &self,
+        source_directory: &std::path::Path,
+        sprite_images: &sprite_image::SpriteImages,
+    ) -> anyhow::Result<iff::IffChunk> {
         assert!(self.sprite_type == SpriteType::Spr1);
 
         let mut frame_datas = std::vec::Vec::new();
@@ -233,13 +475,13 @@ impl Sprite {
                 };
                 let file_path =
                     source_directory.join(frame.sprite_channel_file_path_relative(channel_type, self.chunk_id)?);
-                let mut bmp = read_bmp(&file_path)?;
-                bmp.set_indexed_color(true);
-                let (width, height) = bmp.dimensions();
-                let mut pixels = vec![0u8; usize::try_from(width * height).unwrap()];
-                use image::ImageDecoder;
-                bmp.read_image(&mut pixels).with_context(|| error::file_read_error(&file_path))?;
-                (width, height, pixels)
+                let channel_image = sprite_images.read_channel_image(&file_path)?;
+                let pixels = if self.is_custom_wall_style {
+                    channel_image.into_gray()
+                } else {
+                    channel_image.into_indexed(&file_path)?
+                };
+                (pixels.width(), pixels.height(), pixels.into_raw())
             };
 
             let mut frame_data = std::vec::Vec::<u8>::new();
@@ -250,21 +492,20 @@ impl Sprite {
             let width = usize::try_from(width).unwrap();
             let height = usize::try_from(height).unwrap();
 
-            let transparent_color_index = if self.is_custom_wall_style {
-                255
-            } else {
-                frame.transparent_color_index
-            };
+            let transparent_color_index = frame.transparent_color_index;
 
             let pixels = {
                 // the transmogrifier exports these sprites with pixels of either 128 or 255
-                // but the original iff file is either 0 or 255
+                // but the original iff file is either 0 or 255, with 255 being transparent
                 let mut pixels = pixels;
                 if self.is_custom_wall_style {
+                    let opaque_color_index = if transparent_color_index == 0 { 255 } else { 0 };
                     for pixel in pixels.iter_mut() {
-                        if *pixel != transparent_color_index {
-                            *pixel = 0;
-                        }
+                        *pixel = if *pixel == 255 {
+                            transparent_color_index
+                        } else {
+                            opaque_color_index
+                        };
                     }
                 }
                 pixels
@@ -487,180 +728,171 @@ impl Sprite {
         })
     }
 
-    fn to_spr2_chunk(&self, source_directory: &std::path::Path) -> anyhow::Result<iff::IffChunk> {
+    fn to_spr2_chunk(
+        &self,
+        source_directory: &std::path::Path,
+        channel_generation: ChannelGeneration,
+        sprite_images: &sprite_image::SpriteImages,
+    ) -> anyhow::Result<iff::IffChunk> {
         assert!(self.sprite_type == SpriteType::Spr2);
 
-        let mut frame_datas = std::vec::Vec::new();
-        for frame in &self.sprite_frames {
-            let width = u32::try_from(frame.cropped_width).unwrap();
-            let height = u32::try_from(frame.cropped_height).unwrap();
-            let (pixels_p, pixels_z, pixels_a) = {
-                let file_path_p = source_directory
-                    .join(frame.sprite_channel_file_path_relative(SpriteChannelType::Color, self.chunk_id)?);
-                let file_path_z = source_directory
-                    .join(frame.sprite_channel_file_path_relative(SpriteChannelType::Depth, self.chunk_id)?);
-                let file_path_a = source_directory
-                    .join(frame.sprite_channel_file_path_relative(SpriteChannelType::Alpha, self.chunk_id)?);
-
-                let x = u32::try_from(frame.bounds_left).unwrap();
-                let y = u32::try_from(frame.bounds_top).unwrap();
-
-                let mut bmp_p = read_bmp(&file_path_p)?;
-                let mut bmp_z = read_bmp(&file_path_z)?;
-                let mut bmp_a = read_bmp(&file_path_a)?;
-
-                let pixels_p = read_bmp_rect(&mut bmp_p, x, y, std::cmp::max(width, 1), std::cmp::max(height, 1))
-                    .with_context(|| error::file_read_error(&file_path_p))?;
-                let pixels_z = read_bmp_rect(&mut bmp_z, x, y, std::cmp::max(width, 1), std::cmp::max(height, 1))
-                    .with_context(|| error::file_read_error(&file_path_z))?;
-                let pixels_a = read_bmp_rect(&mut bmp_a, x, y, std::cmp::max(width, 1), std::cmp::max(height, 1))
-                    .with_context(|| error::file_read_error(&file_path_a))?;
-
-                (pixels_p, pixels_z, pixels_a)
-            };
-
-            const SPRITE_FLAGS: u32 = 0b0111;
-            let mut frame_data = std::vec::Vec::<u8>::new();
-            frame_data.extend_from_slice(&u16::try_from(width).unwrap().to_le_bytes());
-            frame_data.extend_from_slice(&u16::try_from(height).unwrap().to_le_bytes());
-            frame_data.extend_from_slice(&SPRITE_FLAGS.to_le_bytes());
-            frame_data.extend_from_slice(&frame.palette_chunk_id.as_i16().to_le_bytes());
-            frame_data.extend_from_slice(&u16::from(frame.transparent_color_index).to_le_bytes());
-            frame_data.extend_from_slice(&u16::try_from(frame.bounds_top).unwrap().to_le_bytes());
-            frame_data.extend_from_slice(&u16::try_from(frame.bounds_left).unwrap().to_le_bytes());
-
-            let width = usize::try_from(width).unwrap();
-            let height = usize::try_from(height).unwrap();
+        use rayon::prelude::*;
+        let frame_datas = self
+            .sprite_frames
+            .par_iter()
+            .map(|frame| -> anyhow::Result<Vec<u8>> {
+                let width = u32::try_from(frame.cropped_width).unwrap();
+                let height = u32::try_from(frame.cropped_height).unwrap();
+                let FrameChannels {
+                    color: pixels_p,
+                    depth: pixels_z,
+                    alpha: pixels_a,
+                    transparent_color_index,
+                } = frame.read_channels(source_directory, self.chunk_id, channel_generation, sprite_images)?;
+
+                const SPRITE_FLAGS: u32 = 0b0111;
+                let mut frame_data = std::vec::Vec::<u8>::new();
+                frame_data.extend_from_slice(&u16::try_from(width).unwrap().to_le_bytes());
+                frame_data.extend_from_slice(&u16::try_from(height).unwrap().to_le_bytes());
+                frame_data.extend_from_slice(&SPRITE_FLAGS.to_le_bytes());
+                frame_data.extend_from_slice(&frame.palette_chunk_id.as_i16().to_le_bytes());
+                frame_data.extend_from_slice(&u16::from(transparent_color_index).to_le_bytes());
+                frame_data.extend_from_slice(&u16::try_from(frame.bounds_top).unwrap().to_le_bytes());
+                frame_data.extend_from_slice(&u16::try_from(frame.bounds_left).unwrap().to_le_bytes());
+
+                let width = usize::try_from(width).unwrap();
+                let height = usize::try_from(height).unwrap();
+
+                enum RowCommand {
+                    Start,
+                    Opaque,
+                    Translucent,
+                    Transparent,
+                    TransparentRows,
+                    End,
+                }
 
-            enum RowCommand {
-                Start,
-                Opaque,
-                Translucent,
-                Transparent,
-                TransparentRows,
-                End,
-            }
+                fn row_command(command: RowCommand, size_or_length: u16) -> u16 {
+                    assert!(size_or_length <= 0b0001111111111111);
+                    let row_command_bits = match command {
+                        RowCommand::Start => 0b0000000000000000,
+                        RowCommand::Opaque => 0b0010000000000000,
+                        RowCommand::Translucent => 0b0100000000000000,
+                        RowCommand::Transparent => 0b0110000000000000,
+                        RowCommand::TransparentRows => 0b1000000000000000,
+                        RowCommand::End => 0b1010000000000000,
+                    };
+                    row_command_bits | size_or_length
+                }
 
-            fn row_command(command: RowCommand, size_or_length: u16) -> u16 {
-                assert!(size_or_length <= 0b0001111111111111);
-                let row_command_bits = match command {
-                    RowCommand::Start => 0b0000000000000000,
-                    RowCommand::Opaque => 0b0010000000000000,
-                    RowCommand::Translucent => 0b0100000000000000,
-                    RowCommand::Transparent => 0b0110000000000000,
-                    RowCommand::TransparentRows => 0b1000000000000000,
-                    RowCommand::End => 0b1010000000000000,
-                };
-                row_command_bits | size_or_length
-            }
+                let mut y = 0;
+                while y < height {
+                    let mut row_commands = std::vec::Vec::new();
 
-            let mut y = 0;
-            while y < height {
-                let mut row_commands = std::vec::Vec::new();
+                    let row_index = y * width;
 
-                let row_index = y * width;
+                    if let Some(i) = pixels_a[row_index..].iter().position(|x| *x != 0) {
+                        let transparent_row_count = i / width;
+                        if transparent_row_count >= 1 {
+                            let row_command_length = u16::try_from(transparent_row_count).unwrap();
+                            let row_command = row_command(RowCommand::TransparentRows, row_command_length);
+                            frame_data.extend_from_slice(&row_command.to_le_bytes());
 
-                if let Some(i) = pixels_a[row_index..].iter().position(|x| *x != 0) {
-                    let transparent_row_count = i / width;
-                    if transparent_row_count >= 1 {
-                        let row_command_length = u16::try_from(transparent_row_count).unwrap();
-                        let row_command = row_command(RowCommand::TransparentRows, row_command_length);
-                        frame_data.extend_from_slice(&row_command.to_le_bytes());
-
-                        y += transparent_row_count;
-                        continue;
+                            y += transparent_row_count;
+                            continue;
+                        }
                     }
-                }
 
-                let mut x = 0;
-                while x < width {
-                    let alpha_pixel = pixels_a[row_index + x] >> 3;
+                    let mut x = 0;
+                    while x < width {
+                        let alpha_pixel = pixels_a[row_index + x] >> 3;
 
-                    if alpha_pixel == 0 {
-                        let mut transparent_width = 1;
-                        while x + transparent_width < width {
-                            let alpha_pixel = pixels_a[row_index + x + transparent_width];
-                            if alpha_pixel == 0 {
-                                transparent_width += 1;
-                            } else {
+                        if alpha_pixel == 0 {
+                            let mut transparent_width = 1;
+                            while x + transparent_width < width {
+                                let alpha_pixel = pixels_a[row_index + x + transparent_width];
+                                if alpha_pixel == 0 {
+                                    transparent_width += 1;
+                                } else {
+                                    break;
+                                }
+                            }
+                            if x + transparent_width == width {
                                 break;
                             }
-                        }
-                        if x + transparent_width == width {
-                            break;
-                        }
 
-                        let row_command_length = u16::try_from(transparent_width).unwrap();
-                        let row_command = row_command(RowCommand::Transparent, row_command_length);
-                        row_commands.extend_from_slice(&row_command.to_le_bytes());
+                            let row_command_length = u16::try_from(transparent_width).unwrap();
+                            let row_command = row_command(RowCommand::Transparent, row_command_length);
+                            row_commands.extend_from_slice(&row_command.to_le_bytes());
 
-                        x += transparent_width;
-                    } else if alpha_pixel < 31 {
-                        let mut translucent_color_width = 1;
-                        while x + translucent_color_width < width {
-                            let alpha_pixel = pixels_a[row_index + x + translucent_color_width] >> 3;
+                            x += transparent_width;
+                        } else if alpha_pixel < 31 {
+                            let mut translucent_color_width = 1;
+                            while x + translucent_color_width < width {
+                                let alpha_pixel = pixels_a[row_index + x + translucent_color_width] >> 3;
 
-                            if alpha_pixel > 0 && alpha_pixel < 31 {
-                                translucent_color_width += 1;
-                            } else {
-                                break;
+                                if alpha_pixel > 0 && alpha_pixel < 31 {
+                                    translucent_color_width += 1;
+                                } else {
+                                    break;
+                                }
                             }
-                        }
 
-                        let row_command_length = u16::try_from(translucent_color_width).unwrap();
-                        let row_command = row_command(RowCommand::Translucent, row_command_length);
-                        row_commands.extend_from_slice(&row_command.to_le_bytes());
+                            let row_command_length = u16::try_from(translucent_color_width).unwrap();
+                            let row_command = row_command(RowCommand::Translucent, row_command_length);
+                            row_commands.extend_from_slice(&row_command.to_le_bytes());
 
-                        for x in x..x + translucent_color_width {
-                            row_commands.push(pixels_z[row_index + x]);
-                            row_commands.push(pixels_p[row_index + x]);
-                            row_commands.push(pixels_a[row_index + x] >> 3);
-                        }
+                            for x in x..x + translucent_color_width {
+                                row_commands.push(pixels_z[row_index + x]);
+                                row_commands.push(pixels_p[row_index + x]);
+                                row_commands.push(pixels_a[row_index + x] >> 3);
+                            }
 
-                        if translucent_color_width % 2 == 1 {
-                            row_commands.push(0);
-                        }
+                            if translucent_color_width % 2 == 1 {
+                                row_commands.push(0);
+                            }
 
-                        x += translucent_color_width;
-                    } else {
-                        let mut color_width = 1;
-                        while x + color_width < width {
-                            let alpha_pixel = pixels_a[row_index + x + color_width] >> 3;
+                            x += translucent_color_width;
+                        } else {
+                            let mut color_width = 1;
+                            while x + color_width < width {
+                                let alpha_pixel = pixels_a[row_index + x + color_width] >> 3;
 
-                            if alpha_pixel == 31 {
-                                color_width += 1;
-                            } else {
-                                break;
+                                if alpha_pixel == 31 {
+                                    color_width += 1;
+                                } else {
+                                    break;
+                                }
                             }
-                        }
 
-                        let row_command_length = u16::try_from(color_width).unwrap();
-                        let row_command = row_command(RowCommand::Opaque, row_command_length);
-                        row_commands.extend_from_slice(&row_command.to_le_bytes());
+                            let row_command_length = u16::try_from(color_width).unwrap();
+                            let row_command = row_command(RowCommand::Opaque, row_command_length);
+                            row_commands.extend_from_slice(&row_command.to_le_bytes());
 
-                        for x in x..x + color_width {
-                            row_commands.push(pixels_z[row_index + x]);
-                            row_commands.push(pixels_p[row_index + x]);
-                        }
+                            for x in x..x + color_width {
+                                row_commands.push(pixels_z[row_index + x]);
+                                row_commands.push(pixels_p[row_index + x]);
+                            }
 
-                        x += color_width;
+                            x += color_width;
+                        }
                     }
-                }
 
-                let row_command_length = 2 + u16::try_from(row_commands.len()).unwrap();
-                let row_command = row_command(RowCommand::Start, row_command_length);
-                frame_data.extend_from_slice(&row_command.to_le_bytes());
+                    let row_command_length = 2 + u16::try_from(row_commands.len()).unwrap();
+                    let row_command = row_command(RowCommand::Start, row_command_length);
+                    frame_data.extend_from_slice(&row_command.to_le_bytes());
 
-                frame_data.extend_from_slice(row_commands.as_slice());
+                    frame_data.extend_from_slice(row_commands.as_slice());
 
-                y += 1;
-            }
+                    y += 1;
+                }
 
-            let row_command = row_command(RowCommand::End, 0);
-            frame_data.extend_from_slice(&row_command.to_le_bytes());
+                let row_command = row_command(RowCommand::End, 0);
+                frame_data.extend_from_slice(&row_command.to_le_bytes());
 
-            frame_datas.push(frame_data);
-        }
+                Ok(frame_data)
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
 
         const SPR2_VERSION: u32 = 1000;
 
@@ -689,130 +921,138 @@ impl Sprite {
     }
 }
 
-fn read_bmp(
-    file_path: &std::path::Path,
-) -> anyhow::Result<image::codecs::bmp::BmpDecoder<std::io::BufReader<std::fs::File>>> {
-    let bmp_buffer =
-        std::io::BufReader::new(std::fs::File::open(file_path).with_context(|| error::file_read_error(file_path))?);
-    let bmp = image::codecs::bmp::BmpDecoder::new(bmp_buffer).with_context(|| error::file_read_error(file_path))?;
-    anyhow::ensure!(
-        bmp.get_palette().is_some(),
-        format!("{} is not an 8-bit bmp", file_path.display())
-    );
-    Ok(bmp)
-}
-
-fn read_bmp_rect(
-    bmp: &mut image::codecs::bmp::BmpDecoder<std::io::BufReader<std::fs::File>>,
-    x: u32,
-    y: u32,
-    width: u32,
-    height: u32,
-) -> anyhow::Result<Vec<u8>> {
-    bmp.set_indexed_color(true);
-    let mut pixels = vec![0u8; usize::try_from(width * height).unwrap()];
-    use image::ImageDecoderRect;
-    bmp.read_rect(x, y, width, height, &mut pixels, usize::try_from(width).unwrap())?;
-    Ok(pixels)
-}
-
-pub fn deserialize_sprites<'de, D>(deserializer: D) -> Result<iff_description::Sprites, D::Error>
-where
-    D: serde::Deserializer<'de>,
-{
-    use serde::Deserialize;
-    let sprites = iff_description::Sprites::deserialize(deserializer)?;
-
-    for sprite in &sprites.sprites {
+pub fn check_sprites(sprites: &[Sprite], diagnostics: &mut diagnostics::Diagnostics) {
+    let sprites_path = diagnostics::ElementPath::root().child("sprites", 0);
+    for (sprite_index, sprite) in sprites.iter().enumerate() {
+        let sprite_path = sprites_path.child("sprite", sprite_index);
         if let Ok(sprite_frames_len) = i32::try_from(sprite.sprite_frames.len()) {
             if sprite.sprite_frame_count != sprite_frames_len {
-                return Err(serde::de::Error::custom(format!(
-                    "frame count of {} does not match amount of frames in sprite {} {}",
-                    sprite.sprite_frame_count,
-                    sprite.chunk_id.as_i16(),
-                    sprite.chunk_label,
-                )));
+                diagnostics.error(
+                    "incorrect-frame-count",
+                    &sprite_path,
+                    format!(
+                        "frame count of {} does not match amount of frames in sprite {} {}",
+                        sprite.sprite_frame_count,
+                        sprite.chunk_id.as_i16(),
+                        sprite.chunk_label,
+                    ),
+                );
             }
         } else {
-            return Err(serde::de::Error::custom(format!(
-                "sprite {} {} has too many frames",
-                sprite.chunk_id.as_i16(),
-                sprite.chunk_label,
-            )));
+            diagnostics.error(
+                "too-many-frames",
+                &sprite_path,
+                format!(
+                    "sprite {} {} has too many frames",
+                    sprite.chunk_id.as_i16(),
+                    sprite.chunk_label,
+                ),
+            );
         }
 
         for (frame, index) in sprite.sprite_frames.iter().zip(0i32..) {
+            let frame_path = sprite_path.child("spriteframe", usize::try_from(index).unwrap());
             if frame.index.as_i32() != index {
-                return Err(serde::de::Error::custom(format!(
-                    "index of {} is incorrect for frame {} of sprite {} {}",
-                    frame.index.as_i32(),
-                    index,
-                    sprite.chunk_id.as_i16(),
-                    sprite.chunk_label,
-                )));
+                diagnostics.error(
+                    "incorrect-frame-index",
+                    &frame_path,
+                    format!(
+                        "index of {} is incorrect for frame {} of sprite {} {}",
+                        frame.index.as_i32(),
+                        index,
+                        sprite.chunk_id.as_i16(),
+                        sprite.chunk_label,
+                    ),
+                );
             }
 
             match sprite.sprite_type {
                 SpriteType::Spr1 => {
                     if frame.sprite_channels.len() != 1 {
-                        return Err(serde::de::Error::custom(format!(
-                            "expected 1 channel in frame {} of sprite {} {}",
-                            frame.index.as_i32(),
-                            sprite.chunk_id.as_i16(),
-                            sprite.chunk_label,
-                        )));
+                        diagnostics.error(
+                            "incorrect-channel-count",
+                            &frame_path,
+                            format!(
+                                "expected 1 channel in frame {} of sprite {} {}",
+                                frame.index.as_i32(),
+                                sprite.chunk_id.as_i16(),
+                                sprite.chunk_label,
+                            ),
+                        );
                     } else if frame.sprite_channels[0].channel_type != SpriteChannelType::Depth {
-                        return Err(serde::de::Error::custom(format!(
-                            "expected depth channel in frame {} of sprite {} {}",
-                            frame.index.as_i32(),
-                            sprite.chunk_id.as_i16(),
-                            sprite.chunk_label,
-                        )));
+                        diagnostics.error(
+                            "incorrect-channel-type",
+                            &frame_path.child("spritechannel", 0),
+                            format!(
+                                "expected depth channel in frame {} of sprite {} {}",
+                                frame.index.as_i32(),
+                                sprite.chunk_id.as_i16(),
+                                sprite.chunk_label,
+                            ),
+                        );
                     }
                 }
                 SpriteType::Spr2 => {
-                    if frame.sprite_channels.len() != 3 {
-                        return Err(serde::de::Error::custom(format!(
-                            "expected 3 channels in frame {} of sprite {} {}",
-                            frame.index.as_i32(),
-                            sprite.chunk_id.as_i16(),
-                            sprite.chunk_label,
-                        )));
+                    // Depth and alpha channels may be left out, they are generated from the color channel
+                    if frame.sprite_channels.is_empty() || frame.sprite_channels.len() > 3 {
+                        diagnostics.error(
+                            "incorrect-channel-count",
+                            &frame_path,
+                            format!(
+                                "expected 1 to 3 channels in frame {} of sprite {} {}",
+                                frame.index.as_i32(),
+                                sprite.chunk_id.as_i16(),
+                                sprite.chunk_label,
+                            ),
+                        );
                     } else {
                         let channel_types = [
                             SpriteChannelType::Color,
                             SpriteChannelType::Depth,
                             SpriteChannelType::Alpha,
                         ];
-                        for (i, channel_type) in channel_types.iter().enumerate() {
-                            if frame.sprite_channels[i].channel_type != *channel_type {
-                                return Err(serde::de::Error::custom(format!(
-                                    "expected {} channel in channel {} of frame {} of sprite {} {}",
-                                    channel_type,
-                                    i,
-                                    frame.index.as_i32(),
-                                    sprite.chunk_id.as_i16(),
-                                    sprite.chunk_label,
-                                )));
+                        let channel_type_order = |channel_type| channel_types.iter().position(|x| *x == channel_type);
+                        for (i, channel) in frame.sprite_channels.iter().enumerate() {
+                            let is_in_order = if i == 0 {
+                                channel.channel_type == SpriteChannelType::Color
+                            } else {
+                                channel_type_order(channel.channel_type)
+                                    > channel_type_order(frame.sprite_channels[i - 1].channel_type)
+                            };
+                            if !is_in_order {
+                                diagnostics.error(
+                                    "incorrect-channel-type",
+                                    &frame_path.child("spritechannel", i),
+                                    format!(
+                                        "unexpected {} channel in channel {} of frame {} of sprite {} {}, expected color, depth and alpha channels in that order",
+                                        channel.channel_type,
+                                        i,
+                                        frame.index.as_i32(),
+                                        sprite.chunk_id.as_i16(),
+                                        sprite.chunk_label,
+                                    ),
+                                );
                             }
                         }
                     }
                 }
             };
 
-            for channel in &frame.sprite_channels {
+            for (i, channel) in frame.sprite_channels.iter().enumerate() {
                 if channel.file_path_relative.is_empty() {
-                    return Err(serde::de::Error::custom(format!(
-                        "no file path found in {} channel of frame {} of sprite {} {}",
-                        channel.channel_type,
-                        frame.index.as_i32(),
-                        sprite.chunk_id.as_i16(),
-                        sprite.chunk_label,
-                    )));
+                    diagnostics.error(
+                        "missing-file-path",
+                        &frame_path.child("spritechannel", i),
+                        format!(
+                            "no file path found in {} channel of frame {} of sprite {} {}",
+                            channel.channel_type,
+                            frame.index.as_i32(),
+                            sprite.chunk_id.as_i16(),
+                            sprite.chunk_label,
+                        ),
+                    );
                 }
             }
         }
     }
-
-    Ok(sprites)
 }
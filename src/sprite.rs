@@ -78,7 +78,7 @@ impl std::fmt::Display for Channel {
     }
 }
 
-#[derive(serde::Serialize, serde::Deserialize)]
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
 pub struct SpriteBounds {
     pub left: i16,
     pub top: i16,
@@ -86,14 +86,14 @@ pub struct SpriteBounds {
     pub bottom: i16,
 }
 
-#[derive(serde::Serialize, serde::Deserialize)]
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
 pub struct SpriteOffsets {
     pub x: i32,
     pub y: i32,
     pub x_flipped: i32,
 }
 
-#[derive(serde::Serialize, serde::Deserialize)]
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
 pub struct SpriteImageDescription {
     pub width: i16,
     pub height: i16,
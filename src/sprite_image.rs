@@ -0,0 +1,406 @@
+use crate::build_cache;
+use crate::error;
+use crate::iff;
+use crate::palt;
+use crate::quantizer;
+use crate::spr;
+use crate::sprite;
+
+use anyhow::Context;
+
+// Sprite channels can be 8-bit bmp files, indexed or grayscale png files, or truecolor png and tga files
+#[derive(Clone)]
+pub enum ChannelImage {
+    Indexed {
+        pixels: image::GrayImage,
+        palette: Vec<[u8; 3]>,
+    },
+    Grayscale(image::GrayImage),
+    Truecolor(image::RgbaImage),
+}
+
+impl ChannelImage {
+    pub fn width(&self) -> u32 {
+        match self {
+            ChannelImage::Indexed { pixels, .. } => pixels.width(),
+            ChannelImage::Grayscale(pixels) => pixels.width(),
+            ChannelImage::Truecolor(pixels) => pixels.width(),
+        }
+    }
+
+    pub fn height(&self) -> u32 {
+        match self {
+            ChannelImage::Indexed { pixels, .. } => pixels.height(),
+            ChannelImage::Grayscale(pixels) => pixels.height(),
+            ChannelImage::Truecolor(pixels) => pixels.height(),
+        }
+    }
+
+    // Depth and alpha channels exported by the Transmogrifier are 8-bit bmp files with a grayscale palette,
+    // so their indices are used as is
+    pub fn into_gray(self) -> image::GrayImage {
+        match self {
+            ChannelImage::Indexed { pixels, .. } => pixels,
+            ChannelImage::Grayscale(pixels) => pixels,
+            ChannelImage::Truecolor(pixels) => image::DynamicImage::ImageRgba8(pixels).to_luma8(),
+        }
+    }
+
+    pub fn into_indexed(self, file_path: &std::path::Path) -> anyhow::Result<image::GrayImage> {
+        match self {
+            ChannelImage::Indexed { pixels, .. } => Ok(pixels),
+            _ => anyhow::bail!("{} is not an 8-bit indexed color image", file_path.display()),
+        }
+    }
+
+    pub fn is_indexed(&self) -> bool {
+        matches!(self, ChannelImage::Indexed { .. })
+    }
+}
+
+fn is_png_file(file_path: &std::path::Path) -> bool {
+    file_path.extension().is_some_and(|x| x.eq_ignore_ascii_case("png"))
+}
+
+fn is_bmp_file(file_path: &std::path::Path) -> bool {
+    file_path.extension().is_some_and(|x| x.eq_ignore_ascii_case("bmp"))
+}
+
+fn read_bmp(file_path: &std::path::Path) -> anyhow::Result<ChannelImage> {
+    let bmp_buffer =
+        std::io::BufReader::new(std::fs::File::open(file_path).with_context(|| error::file_read_error(file_path))?);
+    let mut bmp = image::codecs::bmp::BmpDecoder::new(bmp_buffer).with_context(|| error::file_read_error(file_path))?;
+    let palette = bmp.get_palette().with_context(|| format!("{} is not an 8-bit bmp", file_path.display()))?.to_vec();
+
+    bmp.set_indexed_color(true);
+    use image::ImageDecoder;
+    let (width, height) = bmp.dimensions();
+    let mut pixels = vec![0u8; usize::try_from(width * height).unwrap()];
+    bmp.read_image(&mut pixels).with_context(|| error::file_read_error(file_path))?;
+
+    Ok(ChannelImage::Indexed {
+        pixels: image::GrayImage::from_raw(width, height, pixels).unwrap(),
+        palette,
+    })
+}
+
+fn read_indexed_png(file_path: &std::path::Path) -> anyhow::Result<Option<ChannelImage>> {
+    let png_buffer =
+        std::io::BufReader::new(std::fs::File::open(file_path).with_context(|| error::file_read_error(file_path))?);
+    let mut png = png::Decoder::new(png_buffer).read_info().with_context(|| error::file_read_error(file_path))?;
+    if png.info().color_type != png::ColorType::Indexed {
+        return Ok(None);
+    }
+
+    let (width, height) = png.info().size();
+    let bit_depth = png.info().bit_depth as usize;
+    let palette: Vec<[u8; 3]> = png
+        .info()
+        .palette
+        .as_deref()
+        .with_context(|| format!("{} does not have a palette", file_path.display()))?
+        .chunks_exact(3)
+        .map(|x| [x[0], x[1], x[2]])
+        .collect();
+
+    let mut buffer = vec![0u8; png.output_buffer_size()];
+    let frame = png.next_frame(&mut buffer).with_context(|| error::file_read_error(file_path))?;
+
+    // Indices of less than 8 bits are packed into bytes starting with the most significant bits
+    let pixels_per_byte = 8 / bit_depth;
+    let mask = u8::try_from((1u16 << bit_depth) - 1).unwrap();
+    let mut pixels = Vec::with_capacity(usize::try_from(width * height).unwrap());
+    for row in buffer.chunks(frame.line_size).take(usize::try_from(height).unwrap()) {
+        for x in 0..usize::try_from(width).unwrap() {
+            let shift = 8 - bit_depth * (x % pixels_per_byte + 1);
+            pixels.push((row[x / pixels_per_byte] >> shift) & mask);
+        }
+    }
+
+    Ok(Some(ChannelImage::Indexed {
+        pixels: image::GrayImage::from_raw(width, height, pixels).unwrap(),
+        palette,
+    }))
+}
+
+fn read_channel_image(file_path: &std::path::Path) -> anyhow::Result<ChannelImage> {
+    if is_bmp_file(file_path) {
+        return read_bmp(file_path);
+    }
+    if is_png_file(file_path) {
+        if let Some(channel_image) = read_indexed_png(file_path)? {
+            return Ok(channel_image);
+        }
+    }
+
+    let image = image::open(file_path).with_context(|| error::file_read_error(file_path))?;
+    Ok(match image {
+        image::DynamicImage::ImageLuma8(_) | image::DynamicImage::ImageLuma16(_) => {
+            ChannelImage::Grayscale(image.to_luma8())
+        }
+        _ => ChannelImage::Truecolor(image.to_rgba8()),
+    })
+}
+
+pub fn crop(
+    image: &image::GrayImage,
+    file_path: &std::path::Path,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+) -> anyhow::Result<Vec<u8>> {
+    anyhow::ensure!(
+        x + width <= image.width() && y + height <= image.height(),
+        "{} is smaller than the bounds of its sprite frame",
+        file_path.display()
+    );
+    Ok(image::imageops::crop_imm(image, x, y, width, height).to_image().into_raw())
+}
+
+pub struct QuantizedSprite {
+    pub pixels: image::GrayImage,
+    pub alpha: image::GrayImage,
+    pub transparent_color_index: u8,
+}
+
+// Sprite images are read from their files, unless they were split from the full sprites in memory.
+// Truecolor color channels are quantized when compiling, all frames sharing a palette together.
+#[derive(Default)]
+pub struct SpriteImages {
+    images: std::collections::HashMap<std::path::PathBuf, ChannelImage>,
+    descriptions: std::collections::HashMap<std::path::PathBuf, sprite::SpriteImageDescription>,
+    quantized_sprites: std::collections::HashMap<std::path::PathBuf, QuantizedSprite>,
+    quantized_palettes: std::collections::HashMap<iff::IffChunkId, Vec<[u8; 3]>>,
+}
+
+impl SpriteImages {
+    pub fn insert_image(&mut self, file_path: std::path::PathBuf, channel_image: ChannelImage) {
+        self.images.insert(file_path, channel_image);
+    }
+
+    pub fn insert_description(
+        &mut self,
+        file_path: std::path::PathBuf,
+        sprite_image_description: sprite::SpriteImageDescription,
+    ) {
+        self.descriptions.insert(file_path, sprite_image_description);
+    }
+
+    pub fn extend(&mut self, sprite_images: SpriteImages) {
+        self.images.extend(sprite_images.images);
+        self.descriptions.extend(sprite_images.descriptions);
+        self.quantized_sprites.extend(sprite_images.quantized_sprites);
+        self.quantized_palettes.extend(sprite_images.quantized_palettes);
+    }
+
+    pub fn image(&self, file_path: &std::path::Path) -> Option<&ChannelImage> {
+        self.images.get(file_path)
+    }
+
+    pub fn description(&self, file_path: &std::path::Path) -> Option<&sprite::SpriteImageDescription> {
+        self.descriptions.get(file_path)
+    }
+
+    pub fn read_channel_image(&self, file_path: &std::path::Path) -> anyhow::Result<ChannelImage> {
+        match self.images.get(file_path) {
+            Some(channel_image) => Ok(channel_image.clone()),
+            None => read_channel_image(file_path),
+        }
+    }
+
+    pub fn hash_channel(
+        &self,
+        file_path: &std::path::Path,
+        hasher: &mut build_cache::ContentHasher,
+    ) -> anyhow::Result<()> {
+        match self.images.get(file_path) {
+            Some(ChannelImage::Indexed { pixels, palette }) => {
+                hasher.update(pixels.width().to_le_bytes());
+                hasher.update(pixels.as_raw());
+                hasher.update(palette.concat());
+            }
+            Some(ChannelImage::Grayscale(pixels)) => {
+                hasher.update(pixels.width().to_le_bytes());
+                hasher.update(pixels.as_raw());
+            }
+            Some(ChannelImage::Truecolor(pixels)) => {
+                hasher.update(pixels.width().to_le_bytes());
+                hasher.update(pixels.as_raw());
+            }
+            None => hasher.update_file(file_path)?,
+        }
+        // Quantized pixels depend on the dithering, not only on the palette
+        if let Some(quantized_sprite) = self.quantized_sprites.get(file_path) {
+            hasher.update(quantized_sprite.pixels.as_raw());
+        }
+        Ok(())
+    }
+
+    pub fn read_description(
+        &self,
+        file_path: &std::path::Path,
+    ) -> anyhow::Result<Option<sprite::SpriteImageDescription>> {
+        if let Some(sprite_image_description) = self.descriptions.get(file_path) {
+            return Ok(Some(sprite_image_description.clone()));
+        }
+        if !file_path.is_file() {
+            return Ok(None);
+        }
+        sprite::read_sprite_image_description_file(file_path).map(Some)
+    }
+
+    // Palettes of png files can have less than 256 colors, the unused entries are filled with black
+    pub fn read_palette(&self, file_path: &std::path::Path) -> anyhow::Result<Vec<[u8; 3]>> {
+        let palette = match self.read_channel_image(file_path)? {
+            ChannelImage::Indexed { palette, .. } => palette,
+            _ => anyhow::bail!(
+                "{} is not an 8-bit indexed color image, its palette is only known once it is compiled",
+                file_path.display()
+            ),
+        };
+        if is_bmp_file(file_path) {
+            anyhow::ensure!(
+                palette.len() == usize::from(palt::PALT_COLOR_ENTRY_COUNT),
+                format!("{} does not have a 256 color palette", &file_path.display())
+            );
+        }
+
+        let mut palette = palette;
+        palette.resize(usize::from(palt::PALT_COLOR_ENTRY_COUNT), [0, 0, 0]);
+        Ok(palette)
+    }
+
+    pub fn quantized(&self, color_sprite_file_path: &std::path::Path) -> Option<&QuantizedSprite> {
+        self.quantized_sprites.get(color_sprite_file_path)
+    }
+
+    pub fn quantized_palette(&self, palette_id: iff::IffChunkId) -> Option<&Vec<[u8; 3]>> {
+        self.quantized_palettes.get(&palette_id)
+    }
+
+    pub fn quantize_truecolor_sprites(
+        &mut self,
+        source_directory: &std::path::Path,
+        sprites: &[spr::Sprite],
+        channel_generation: spr::ChannelGeneration,
+        sprite_quantization: &quantizer::SpriteQuantization,
+    ) -> anyhow::Result<()> {
+        struct TruecolorSprite {
+            file_path: std::path::PathBuf,
+            color: quantizer::R5g6b5Image,
+            alpha: image::GrayImage,
+        }
+
+        let mut palette_groups: Vec<(iff::IffChunkId, Vec<TruecolorSprite>, Option<std::path::PathBuf>)> = Vec::new();
+        for sprite in sprites {
+            if sprite.sprite_type == spr::SpriteType::Spr1 {
+                continue;
+            }
+            let group_index = match palette_groups.iter().position(|x| x.0 == sprite.palette_chunk_id) {
+                Some(group_index) => group_index,
+                None => {
+                    palette_groups.push((sprite.palette_chunk_id, Vec::new(), None));
+                    palette_groups.len() - 1
+                }
+            };
+            let (_, truecolor_sprites, indexed_sprite_file_path) = &mut palette_groups[group_index];
+
+            for frame in &sprite.sprite_frames {
+                let file_path = source_directory
+                    .join(frame.sprite_channel_file_path_relative(spr::SpriteChannelType::Color, sprite.chunk_id)?);
+                // Bmp files are always indexed, so they don't need to be read here
+                if is_bmp_file(&file_path) {
+                    indexed_sprite_file_path.get_or_insert(file_path);
+                    continue;
+                }
+                let channel_image = self.read_channel_image(&file_path)?;
+                if channel_image.is_indexed() {
+                    indexed_sprite_file_path.get_or_insert(file_path);
+                    continue;
+                }
+
+                let (width, height) = (channel_image.width(), channel_image.height());
+                let (color, alpha) = match channel_image {
+                    ChannelImage::Truecolor(pixels) => {
+                        let alpha =
+                            image::GrayImage::from_fn(width, height, |x, y| image::Luma([pixels.get_pixel(x, y)[3]]));
+                        (image::DynamicImage::ImageRgba8(pixels).to_rgb8(), alpha)
+                    }
+                    channel_image => {
+                        let pixels = channel_image.into_gray();
+                        let alpha = image::GrayImage::from_pixel(width, height, image::Luma([255]));
+                        (image::DynamicImage::ImageLuma8(pixels).to_rgb8(), alpha)
+                    }
+                };
+                // An alpha channel file replaces the alpha of the color channel
+                let alpha = match frame.listed_sprite_channel_file_path(
+                    source_directory,
+                    spr::SpriteChannelType::Alpha,
+                    channel_generation.alpha.is_some(),
+                ) {
+                    Some(alpha_file_path) => self.read_channel_image(&alpha_file_path)?.into_gray(),
+                    None => alpha,
+                };
+                anyhow::ensure!(
+                    alpha.dimensions() == (width, height),
+                    "alpha channel of {} does not have the same size",
+                    file_path.display()
+                );
+
+                truecolor_sprites.push(TruecolorSprite {
+                    file_path,
+                    color: quantizer::dither_color_sprite_to_r5g6b5(color, sprite_quantization.dithering),
+                    alpha,
+                });
+            }
+        }
+
+        for (palette_id, truecolor_sprites, indexed_sprite_file_path) in palette_groups {
+            let Some(truecolor_sprite) = truecolor_sprites.first() else {
+                continue;
+            };
+            if let Some(indexed_sprite_file_path) = indexed_sprite_file_path {
+                anyhow::bail!(
+                    "palette {} is shared by the truecolor sprite {} and the indexed sprite {}, \
+                     convert them to the same kind",
+                    palette_id.as_i16(),
+                    truecolor_sprite.file_path.display(),
+                    indexed_sprite_file_path.display()
+                );
+            }
+
+            let alphas: Vec<_> = truecolor_sprites
+                .iter()
+                .map(|x| image::DynamicImage::ImageLuma8(x.alpha.clone()).into_rgb32f())
+                .collect();
+
+            let mut histogram = quantizer::Histogram::new();
+            for (truecolor_sprite, alpha) in truecolor_sprites.iter().zip(&alphas) {
+                histogram.add_colors(&truecolor_sprite.color, alpha);
+            }
+            let mut quantizer = histogram
+                .finalize(
+                    sprite_quantization.dithering,
+                    sprite_quantization.color_space,
+                    &sprite_quantization.palette_layout,
+                )
+                .with_context(|| format!("Failed to quantize sprites of palette {}", palette_id.as_i16()))?;
+
+            for (truecolor_sprite, alpha) in truecolor_sprites.into_iter().zip(&alphas) {
+                let pixels = quantizer.quantize(&truecolor_sprite.color, alpha);
+                self.quantized_sprites.insert(
+                    truecolor_sprite.file_path,
+                    QuantizedSprite {
+                        pixels,
+                        alpha: truecolor_sprite.alpha,
+                        transparent_color_index: quantizer.transparent_color_index,
+                    },
+                );
+            }
+            self.quantized_palettes.insert(palette_id, quantizer.palette);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,153 @@
+use crate::compiler;
+use crate::project;
+use crate::xml_updater;
+
+const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+
+#[derive(PartialEq)]
+struct FileState {
+    modified: Option<std::time::SystemTime>,
+    len: u64,
+}
+
+type Snapshot = std::collections::BTreeMap<std::path::PathBuf, FileState>;
+
+// Files can disappear while they are being read, which is not an error as the snapshot is taken again shortly
+fn add_to_snapshot(path: &std::path::Path, snapshot: &mut Snapshot) {
+    if path.is_dir() {
+        let Ok(entries) = std::fs::read_dir(path) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            add_to_snapshot(&entry.path(), snapshot);
+        }
+    } else if let Ok(metadata) = std::fs::metadata(path) {
+        snapshot.insert(
+            path.to_owned(),
+            FileState {
+                modified: metadata.modified().ok(),
+                len: metadata.len(),
+            },
+        );
+    }
+}
+
+fn take_snapshot(paths: &[std::path::PathBuf]) -> Snapshot {
+    let mut snapshot = Snapshot::new();
+    for path in paths {
+        add_to_snapshot(path, &mut snapshot);
+    }
+    snapshot
+}
+
+#[derive(Copy, Clone)]
+enum Change {
+    Description,
+    SplitSprites,
+    Renders,
+}
+
+struct WatchedFiles {
+    renders: Vec<std::path::PathBuf>,
+    split_sprites: Vec<std::path::PathBuf>,
+    description: Vec<std::path::PathBuf>,
+}
+
+#[derive(PartialEq)]
+struct Snapshots {
+    renders: Snapshot,
+    split_sprites: Snapshot,
+    description: Snapshot,
+}
+
+impl WatchedFiles {
+    fn take_snapshots(&self) -> Snapshots {
+        Snapshots {
+            renders: take_snapshot(&self.renders),
+            split_sprites: take_snapshot(&self.split_sprites),
+            description: take_snapshot(&self.description),
+        }
+    }
+}
+
+impl Snapshots {
+    // Only the change needing the most steps is returned, as its steps include those of the others
+    fn change(&self, previous: &Snapshots) -> Option<Change> {
+        if self.renders != previous.renders {
+            Some(Change::Renders)
+        } else if self.split_sprites != previous.split_sprites {
+            Some(Change::SplitSprites)
+        } else if self.description != previous.description {
+            Some(Change::Description)
+        } else {
+            None
+        }
+    }
+}
+
+fn rebuild(source_directory: &std::path::Path, object_name: &str, change: Change) -> anyhow::Result<()> {
+    let description_file_path = project::description_file_path(source_directory, object_name);
+    match change {
+        Change::Renders => compiler::build(source_directory, object_name, true),
+        Change::SplitSprites => {
+            // Project manifests can't be updated, the split sprites replace their sprites when compiling instead
+            if !project::is_project_manifest(&description_file_path) {
+                xml_updater::update(source_directory, object_name, None)?;
+            }
+            compiler::compile(&description_file_path, false)
+        }
+        Change::Description => compiler::compile(&description_file_path, false),
+    }
+}
+
+pub fn watch(source_directory: &std::path::Path, object_name: &str) -> anyhow::Result<()> {
+    let watched_files = WatchedFiles {
+        renders: vec![
+            source_directory.join(format!("{} - full sprites", object_name)),
+            source_directory.join(format!("{} - object description", object_name)).with_extension("json"),
+        ],
+        split_sprites: vec![source_directory.join(format!("{} - sprites", object_name))],
+        description: vec![project::description_file_path(source_directory, object_name)],
+    };
+
+    let description_file_path = project::description_file_path(source_directory, object_name);
+    let description_file_name = description_file_path.file_name().unwrap_or_default().to_string_lossy();
+
+    println!("Watching {} for changes to {}", source_directory.display(), object_name);
+    let mut previous_snapshots = watched_files.take_snapshots();
+    loop {
+        std::thread::sleep(POLL_INTERVAL);
+        let mut snapshots = watched_files.take_snapshots();
+        if snapshots.change(&previous_snapshots).is_none() {
+            continue;
+        }
+
+        // Renders are written one after another, so files have to stop changing before rebuilding
+        loop {
+            std::thread::sleep(POLL_INTERVAL);
+            let next_snapshots = watched_files.take_snapshots();
+            if next_snapshots == snapshots {
+                break;
+            }
+            snapshots = next_snapshots;
+        }
+        let Some(change) = snapshots.change(&previous_snapshots) else {
+            continue;
+        };
+
+        match change {
+            Change::Renders => println!("Renders changed, splitting, updating xml and compiling"),
+            Change::SplitSprites => println!("Split sprites changed, updating xml and compiling"),
+            Change::Description => println!("{} changed, compiling", description_file_name),
+        }
+
+        let start = std::time::Instant::now();
+        match rebuild(source_directory, object_name, change) {
+            Ok(()) => println!("Done in {:.1}s", start.elapsed().as_secs_f64()),
+            Err(error) => eprintln!("Error: {:#}", error),
+        }
+
+        // Files written while rebuilding are not changes to rebuild from
+        previous_snapshots = watched_files.take_snapshots();
+    }
+}
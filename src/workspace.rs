@@ -0,0 +1,156 @@
+use crate::compiler;
+use crate::error;
+use crate::project;
+use crate::splitter;
+use crate::xml_updater;
+
+use anyhow::Context;
+
+// The format string and creator name are shared by every object, unless an object sets its own
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Workspace {
+    format_string: Option<String>,
+    creator_name: Option<String>,
+    #[serde(rename = "object", default)]
+    objects: Vec<WorkspaceObject>,
+}
+
+// The first variant is the one described by the xml, the others are compiled from it
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WorkspaceObject {
+    source_directory: std::path::PathBuf,
+    object_name: String,
+    #[serde(default)]
+    variants: Vec<String>,
+    format_string: Option<String>,
+    creator_name: Option<String>,
+}
+
+struct BuildResult {
+    object_name: String,
+    variant: Option<String>,
+    duration: std::time::Duration,
+    result: anyhow::Result<()>,
+}
+
+fn build_object(
+    workspace_directory: &std::path::Path,
+    workspace: &Workspace,
+    object: &WorkspaceObject,
+) -> Vec<BuildResult> {
+    let variants: Vec<Option<&str>> = if object.variants.is_empty() {
+        vec![None]
+    } else {
+        object.variants.iter().map(|x| Some(x.as_str())).collect()
+    };
+    let source_directory = workspace_directory.join(&object.source_directory);
+    let format_string = object.format_string.as_ref().or(workspace.format_string.as_ref());
+    let creator_name = object.creator_name.as_ref().or(workspace.creator_name.as_ref());
+
+    // Objects without renders are only compiled
+    let object_description_file_path =
+        source_directory.join(format!("{} - object description", object.object_name)).with_extension("json");
+    let has_renders = object_description_file_path.is_file();
+
+    let mut results = Vec::new();
+    for (i, variant) in variants.iter().enumerate() {
+        let start = std::time::Instant::now();
+        let result = (|| {
+            let format_string = format_string
+                .with_context(|| format!("Failed to find a format string for object {}", object.object_name))?;
+            let creator_name = creator_name
+                .with_context(|| format!("Failed to find a creator name for object {}", object.object_name))?;
+
+            if has_renders {
+                splitter::split(&source_directory, &object.object_name, *variant, false, None)?;
+                // The xml is updated from the sprites of the first variant. Project manifests can't be updated, the
+                // split sprites of the first variant replace their sprites when compiling instead
+                let description_file_path = project::description_file_path(&source_directory, &object.object_name);
+                if i == 0 && !project::is_project_manifest(&description_file_path) {
+                    xml_updater::update(&source_directory, &object.object_name, *variant)?;
+                }
+            }
+
+            compiler::compile_advanced(
+                &source_directory,
+                format_string,
+                creator_name,
+                &object.object_name,
+                variants[0].zip(*variant),
+                false,
+            )
+        })();
+
+        let failed = result.is_err();
+        results.push(BuildResult {
+            object_name: object.object_name.clone(),
+            variant: variant.map(|x| x.to_owned()),
+            duration: start.elapsed(),
+            result,
+        });
+
+        // Every variant is compiled from the xml of the first one
+        if i == 0 && failed {
+            for variant in &variants[1..] {
+                results.push(BuildResult {
+                    object_name: object.object_name.clone(),
+                    variant: variant.map(|x| x.to_owned()),
+                    duration: std::time::Duration::ZERO,
+                    result: Err(anyhow::anyhow!("Skipped as the first variant failed")),
+                });
+            }
+            break;
+        }
+    }
+    results
+}
+
+fn print_summary(results: &[BuildResult]) {
+    let rows: Vec<[String; 3]> = results
+        .iter()
+        .map(|x| {
+            [
+                x.object_name.clone(),
+                x.variant.clone().unwrap_or_default(),
+                match &x.result {
+                    Ok(()) => format!("ok in {:.1}s", x.duration.as_secs_f64()),
+                    Err(error) => format!("failed: {:#}", error),
+                },
+            ]
+        })
+        .collect();
+
+    let header = ["Object".to_owned(), "Variant".to_owned(), "Result".to_owned()];
+    let object_width = rows.iter().chain([&header]).map(|x| x[0].chars().count()).max().unwrap_or(0);
+    let variant_width = rows.iter().chain([&header]).map(|x| x[1].chars().count()).max().unwrap_or(0);
+    for row in [&header].into_iter().chain(&rows) {
+        println!("{:object_width$}  {:variant_width$}  {}", row[0], row[1], row[2]);
+    }
+}
+
+pub fn build_all(workspace_file_path: &std::path::Path) -> anyhow::Result<()> {
+    let toml_string =
+        std::fs::read_to_string(workspace_file_path).with_context(|| error::file_read_error(workspace_file_path))?;
+    let workspace = toml::from_str::<Workspace>(&toml_string)
+        .with_context(|| format!("Failed to deserialize toml file {}", workspace_file_path.display()))?;
+    let workspace_directory = workspace_file_path.parent().with_context(|| {
+        format!(
+            "Failed to get workspace directory from workspace file path {}",
+            workspace_file_path.display()
+        )
+    })?;
+
+    let mut results = Vec::new();
+    for object in &workspace.objects {
+        println!("Building {}", object.object_name);
+        results.extend(build_object(workspace_directory, &workspace, object));
+    }
+
+    print_summary(&results);
+
+    let failed_count = results.iter().filter(|x| x.result.is_err()).count();
+    anyhow::ensure!(failed_count == 0, "{} of {} builds failed", failed_count, results.len());
+    Ok(())
+}
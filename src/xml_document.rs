@@ -0,0 +1,381 @@
+use crate::diagnostics;
+
+use anyhow::Context;
+
+struct XmlAttribute {
+    name: String,
+    value: String,
+    span: std::ops::Range<usize>,
+    value_span: std::ops::Range<usize>,
+}
+
+pub struct XmlElement {
+    name: String,
+    span: std::ops::Range<usize>,
+    start_tag: std::ops::Range<usize>,
+    attributes: Vec<XmlAttribute>,
+    children: Vec<XmlElement>,
+}
+
+impl XmlElement {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn attributes(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.attributes.iter().map(|x| (x.name.as_str(), x.value.as_str()))
+    }
+
+    pub fn children(&self) -> &[XmlElement] {
+        &self.children
+    }
+
+    fn attribute(&self, name: &str) -> Option<&XmlAttribute> {
+        self.attributes.iter().find(|x| x.name == name)
+    }
+
+    fn is_empty_element(&self) -> bool {
+        self.start_tag == self.span
+    }
+}
+
+// A parsed xml document that remembers where every element and attribute is in the original text,
+// so that a changed description can be written back without losing comments, formatting or
+// attribute order.
+pub struct XmlDocument {
+    text: String,
+    root: XmlElement,
+}
+
+struct Edit {
+    span: std::ops::Range<usize>,
+    replacement: String,
+}
+
+impl XmlDocument {
+    pub fn parse(text: &str) -> anyhow::Result<XmlDocument> {
+        let mut reader = quick_xml::Reader::from_str(text);
+        reader.check_end_names(true);
+
+        let mut stack: Vec<XmlElement> = Vec::new();
+        let mut root = None;
+        loop {
+            let start = reader.buffer_position();
+            let event = reader.read_event().context("Failed to parse xml")?;
+            let end = reader.buffer_position();
+            match event {
+                quick_xml::events::Event::Start(element) | quick_xml::events::Event::Empty(element) => {
+                    let is_empty = matches!(text.as_bytes().get(end.wrapping_sub(2)), Some(b'/'));
+                    let element = XmlElement {
+                        name: String::from_utf8_lossy(element.name().as_ref()).into_owned(),
+                        span: start..end,
+                        start_tag: start..end,
+                        attributes: parse_attributes(text, start..end)?,
+                        children: Vec::new(),
+                    };
+                    if is_empty {
+                        if let Some(parent) = stack.last_mut() {
+                            parent.children.push(element);
+                        } else {
+                            root.get_or_insert(element);
+                        }
+                    } else {
+                        stack.push(element);
+                    }
+                }
+                quick_xml::events::Event::End(_) => {
+                    let mut element = stack.pop().context("Failed to parse xml")?;
+                    element.span.end = end;
+                    if let Some(parent) = stack.last_mut() {
+                        parent.children.push(element);
+                    } else {
+                        root.get_or_insert(element);
+                    }
+                }
+                quick_xml::events::Event::Eof => break,
+                _ => (),
+            }
+        }
+
+        Ok(XmlDocument {
+            text: text.to_owned(),
+            root: root.context("Failed to find xml root element")?,
+        })
+    }
+
+    pub fn element_offsets(&self) -> Vec<(diagnostics::ElementPath, usize)> {
+        fn visit(
+            element: &XmlElement,
+            path: &diagnostics::ElementPath,
+            offsets: &mut Vec<(diagnostics::ElementPath, usize)>,
+        ) {
+            let mut child_counts = std::collections::HashMap::new();
+            for child in &element.children {
+                let index = child_counts.entry(child.name.as_str()).or_insert(0);
+                let child_path = path.child(&child.name, *index);
+                *index += 1;
+                offsets.push((child_path.clone(), child.span.start));
+                visit(child, &child_path, offsets);
+            }
+        }
+
+        let mut offsets = Vec::new();
+        visit(&self.root, &diagnostics::ElementPath::root(), &mut offsets);
+        offsets
+    }
+
+    pub fn root(&self) -> &XmlElement {
+        &self.root
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    // Returns the original text with the changes needed to make it match the updated text.
+    // Only the values of attributes that actually changed are rewritten, and only elements that
+    // were added or removed are inserted or deleted.
+    pub fn merge(&self, updated_text: &str) -> anyhow::Result<String> {
+        self.merge_with(updated_text, attribute_values_equal)
+    }
+
+    // Like merge, but also rewrites numbers that are written differently
+    pub fn merge_exactly(&self, updated_text: &str) -> anyhow::Result<String> {
+        self.merge_with(updated_text, |a, b| a == b)
+    }
+
+    fn merge_with(&self, updated_text: &str, values_equal: fn(&str, &str) -> bool) -> anyhow::Result<String> {
+        let updated = XmlDocument::parse(updated_text)?;
+
+        let mut edits = Vec::new();
+        self.merge_element(&self.root, &updated, &updated.root, values_equal, &mut edits);
+
+        let mut merged_text = String::with_capacity(self.text.len());
+        let mut position = 0;
+        for edit in &edits {
+            anyhow::ensure!(edit.span.start >= position, "Failed to merge xml, changes overlap");
+            merged_text += &self.text[position..edit.span.start];
+            merged_text += &edit.replacement;
+            position = edit.span.end;
+        }
+        merged_text += &self.text[position..];
+        Ok(merged_text)
+    }
+
+    fn merge_element(
+        &self,
+        element: &XmlElement,
+        updated: &XmlDocument,
+        updated_element: &XmlElement,
+        values_equal: fn(&str, &str) -> bool,
+        edits: &mut Vec<Edit>,
+    ) {
+        if element.name != updated_element.name || (element.is_empty_element() && !updated_element.children.is_empty())
+        {
+            edits.push(Edit {
+                span: element.span.clone(),
+                replacement: updated.reindented_element(updated_element, &self.indentation(element.span.start)),
+            });
+            return;
+        }
+
+        // Children are matched by name and id so that entries which were added, removed or
+        // replaced only affect their own elements
+        let keys = child_keys(&element.children);
+        let updated_keys = child_keys(&updated_element.children);
+        let matches: Vec<Option<usize>> = updated_keys.iter().map(|x| keys.iter().position(|y| y == x)).collect();
+
+        if !matches.iter().flatten().collect::<Vec<_>>().windows(2).all(|x| x[0] < x[1]) {
+            edits.push(Edit {
+                span: element.span.clone(),
+                replacement: updated.reindented_element(updated_element, &self.indentation(element.span.start)),
+            });
+            return;
+        }
+
+        for updated_attribute in &updated_element.attributes {
+            match element.attribute(&updated_attribute.name) {
+                Some(attribute) => {
+                    if !values_equal(&attribute.value, &updated_attribute.value) {
+                        edits.push(Edit {
+                            span: attribute.value_span.clone(),
+                            replacement: quick_xml::escape::escape(&updated_attribute.value).into_owned(),
+                        });
+                    }
+                }
+                None => {
+                    let position = element
+                        .attributes
+                        .last()
+                        .map(|x| x.span.end)
+                        .unwrap_or(element.start_tag.start + 1 + element.name.len());
+                    edits.push(Edit {
+                        span: position..position,
+                        replacement: format!(
+                            " {}=\"{}\"",
+                            updated_attribute.name,
+                            quick_xml::escape::escape(&updated_attribute.value)
+                        ),
+                    });
+                }
+            }
+        }
+        for attribute in &element.attributes {
+            if updated_element.attribute(&attribute.name).is_none() {
+                let start = self.text[..attribute.span.start].trim_end().len();
+                edits.push(Edit {
+                    span: start..attribute.span.end,
+                    replacement: String::new(),
+                });
+            }
+        }
+
+        let child_indentation = match element.children.first() {
+            Some(child) => self.indentation(child.span.start),
+            None => self.indentation(element.span.start) + "  ",
+        };
+
+        let mut insert_position = element.start_tag.end;
+        for (updated_child, child_index) in updated_element.children.iter().zip(&matches) {
+            match child_index {
+                Some(child_index) => {
+                    let child = &element.children[*child_index];
+                    self.merge_element(child, updated, updated_child, values_equal, edits);
+                    insert_position = child.span.end;
+                }
+                None => {
+                    edits.push(Edit {
+                        span: insert_position..insert_position,
+                        replacement: format!(
+                            "\n{}{}",
+                            child_indentation,
+                            updated.reindented_element(updated_child, &child_indentation)
+                        ),
+                    });
+                }
+            }
+        }
+        for (child_index, child) in element.children.iter().enumerate() {
+            if !matches.contains(&Some(child_index)) {
+                let line_start = self.text[..child.span.start].trim_end_matches([' ', '\t']);
+                let start = if line_start.ends_with('\n') {
+                    line_start.trim_end_matches(['\n', '\r']).len()
+                } else {
+                    child.span.start
+                };
+                edits.push(Edit {
+                    span: start..child.span.end,
+                    replacement: String::new(),
+                });
+            }
+        }
+
+        edits.sort_by_key(|x| x.span.start);
+    }
+
+    fn indentation(&self, position: usize) -> String {
+        let line_start = self.text[..position].rfind('\n').map(|x| x + 1).unwrap_or(0);
+        self.text[line_start..position].chars().take_while(|x| x.is_whitespace()).collect()
+    }
+
+    fn reindented_element(&self, element: &XmlElement, indentation: &str) -> String {
+        let original_indentation = self.indentation(element.span.start);
+        let element_text = &self.text[element.span.clone()];
+        let mut lines = element_text.split('\n');
+        let mut reindented_text = lines.next().unwrap_or_default().to_owned();
+        for line in lines {
+            reindented_text += "\n";
+            reindented_text += indentation;
+            reindented_text += line.strip_prefix(original_indentation.as_str()).unwrap_or(line);
+        }
+        reindented_text
+    }
+}
+
+fn parse_attributes(text: &str, start_tag: std::ops::Range<usize>) -> anyhow::Result<Vec<XmlAttribute>> {
+    let tag = &text.as_bytes()[..start_tag.end];
+    let mut position = start_tag.start + 1;
+    while position < tag.len() && !tag[position].is_ascii_whitespace() && !matches!(tag[position], b'/' | b'>') {
+        position += 1;
+    }
+
+    let mut attributes = Vec::new();
+    loop {
+        while position < tag.len() && tag[position].is_ascii_whitespace() {
+            position += 1;
+        }
+        if position >= tag.len() || matches!(tag[position], b'/' | b'>') {
+            break;
+        }
+
+        let name_start = position;
+        while position < tag.len() && !tag[position].is_ascii_whitespace() && tag[position] != b'=' {
+            position += 1;
+        }
+        let name = text[name_start..position].to_owned();
+        while position < tag.len() && (tag[position].is_ascii_whitespace() || tag[position] == b'=') {
+            position += 1;
+        }
+
+        let quote = *tag.get(position).context("Failed to parse xml attribute")?;
+        anyhow::ensure!(
+            quote == b'"' || quote == b'\'',
+            "Failed to parse value of xml attribute {}",
+            name
+        );
+        let value_start = position + 1;
+        let value_end = value_start
+            + tag[value_start..]
+                .iter()
+                .position(|x| *x == quote)
+                .with_context(|| format!("Failed to parse value of xml attribute {}", name))?;
+        position = value_end + 1;
+
+        attributes.push(XmlAttribute {
+            value: quick_xml::escape::unescape(&text[value_start..value_end])
+                .with_context(|| format!("Failed to parse value of xml attribute {}", name))?
+                .into_owned(),
+            name,
+            span: name_start..position,
+            value_span: value_start..value_end,
+        });
+    }
+    Ok(attributes)
+}
+
+fn child_keys(children: &[XmlElement]) -> Vec<(&str, Option<&str>, usize)> {
+    let mut counts = std::collections::HashMap::new();
+    children
+        .iter()
+        .map(|x| {
+            let id = x.attribute("id").map(|x| x.value.as_str());
+            let count = counts.entry((x.name.as_str(), id)).or_insert(0);
+            *count += 1;
+            (x.name.as_str(), id, *count - 1)
+        })
+        .collect()
+}
+
+pub fn attribute_values_equal(a: &str, b: &str) -> bool {
+    // The Transmogrifier writes floats with a fixed amount of decimals, which should not be
+    // considered a change when the value itself is the same
+    a == b || matches!((a.parse::<f64>(), b.parse::<f64>()), (Ok(a), Ok(b)) if a == b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge_reordered_children_with_changed_attribute() {
+        let document =
+            XmlDocument::parse("<sprites a=\"1\">\n  <sprite id=\"2\"/>\n  <sprite id=\"1\"/>\n</sprites>\n").unwrap();
+        let merged = document
+            .merge("<sprites a=\"2\">\n  <sprite id=\"1\"/>\n  <sprite id=\"2\"/>\n</sprites>\n")
+            .unwrap();
+        assert_eq!(
+            merged,
+            "<sprites a=\"2\">\n  <sprite id=\"1\"/>\n  <sprite id=\"2\"/>\n</sprites>\n"
+        );
+    }
+}
@@ -1,14 +1,18 @@
 use crate::error;
 use crate::iff;
 use crate::iff_description;
+use crate::project;
 use crate::spr;
 use crate::sprite;
+use crate::sprite_image;
 
 use anyhow::Context;
 
-fn build_sprite_description(
+pub fn build_sprite_description(
     source_directory: &std::path::Path,
     tile_directory: &std::path::Path,
+    chunk_id: iff::IffChunkId,
+    sprite_images: &sprite_image::SpriteImages,
 ) -> anyhow::Result<spr::Sprite> {
     let mut sprite_frames = Vec::new();
     let mut sprite_frame_index = 0;
@@ -23,10 +27,9 @@ fn build_sprite_description(
         for rotation in rotations {
             let sprite_description_file_path =
                 sprite::sprite_description_file_path(tile_directory, zoom_level, rotation);
-            if !sprite_description_file_path.is_file() {
+            let Some(sprite_description) = sprite_images.read_description(&sprite_description_file_path)? else {
                 continue;
-            }
-            let sprite_description = sprite::read_sprite_image_description_file(&sprite_description_file_path)?;
+            };
 
             let sprite_p_file_path =
                 sprite::sprite_channel_file_path(tile_directory, zoom_level, rotation, sprite::Channel::Color);
@@ -53,15 +56,6 @@ fn build_sprite_description(
     }
     let chunk_label = tile_directory.file_name().unwrap().to_str().unwrap();
 
-    let chunk_id = {
-        let sprite_id_file_path = tile_directory.join("sprite id").with_extension("json");
-        let json_string = std::fs::read_to_string(&sprite_id_file_path)
-            .with_context(|| error::file_read_error(&sprite_id_file_path))?;
-
-        serde_json::from_str::<iff::IffChunkId>(&json_string)
-            .with_context(|| format!("Failed to deserialize json file {}", sprite_id_file_path.display()))?
-    };
-
     Ok(spr::Sprite::new(
         chunk_label,
         chunk_id,
@@ -70,35 +64,84 @@ fn build_sprite_description(
     ))
 }
 
-pub fn update(source_directory: &std::path::Path, object_name: &str, variant: Option<&str>) -> anyhow::Result<()> {
-    let xml_file_path = source_directory.join(object_name).with_extension("xml");
+pub fn replace_sprites(iff_description: &mut iff_description::IffDescription, mut new_sprites: Vec<spr::Sprite>) {
+    let sprite_id_set: std::collections::HashSet<_> = new_sprites.iter().map(|x| x.chunk_id).collect();
+    assert!(sprite_id_set.len() == new_sprites.len());
+    iff_description.sprites.sprites.retain(|x| !sprite_id_set.contains(&x.chunk_id));
+    iff_description.sprites.sprites.append(&mut new_sprites);
+    iff_description.sprites.sprites.sort_by_key(|x| x.chunk_id.as_i16());
+}
 
-    let mut iff_description = iff_description::IffDescription::open(&xml_file_path)
-        .with_context(|| format!("Failed to open xml file {}", xml_file_path.display()))?;
+fn split_sprites_directory(
+    source_directory: &std::path::Path,
+    object_name: &str,
+    variant: Option<&str>,
+) -> std::path::PathBuf {
+    let object_name = if let Some(variant) = variant {
+        format!("{} - {}", object_name, variant)
+    } else {
+        object_name.to_owned()
+    };
+    source_directory.join(format!("{} - sprites", object_name))
+}
 
+fn read_split_sprites(split_sprites_directory: &std::path::Path) -> anyhow::Result<Vec<spr::Sprite>> {
+    let source_directory = split_sprites_directory.parent().unwrap();
     let mut new_sprites = Vec::new();
-
-    let split_sprites_directory = {
-        let object_name = if let Some(variant) = variant {
-            format!("{} - {}", object_name, variant)
-        } else {
-            object_name.to_owned()
-        };
-        source_directory.join(format!("{} - sprites", object_name))
-    };
     for entry in std::fs::read_dir(split_sprites_directory)? {
         let path = entry?.path();
         if !path.is_dir() {
             continue;
         }
-        new_sprites.push(build_sprite_description(source_directory, &path)?);
+
+        let chunk_id = {
+            let sprite_id_file_path = path.join("sprite id").with_extension("json");
+            let json_string = std::fs::read_to_string(&sprite_id_file_path)
+                .with_context(|| error::file_read_error(&sprite_id_file_path))?;
+
+            serde_json::from_str::<iff::IffChunkId>(&json_string)
+                .with_context(|| format!("Failed to deserialize json file {}", sprite_id_file_path.display()))?
+        };
+
+        new_sprites.push(build_sprite_description(
+            source_directory,
+            &path,
+            chunk_id,
+            &sprite_image::SpriteImages::default(),
+        )?);
     }
+    Ok(new_sprites)
+}
 
-    let sprite_id_set: std::collections::HashSet<_> = new_sprites.iter().map(|x| x.chunk_id).collect();
-    assert!(sprite_id_set.len() == new_sprites.len());
-    iff_description.sprites.sprites.retain(|x| !sprite_id_set.contains(&x.chunk_id));
-    iff_description.sprites.sprites.append(&mut new_sprites);
-    iff_description.sprites.sprites.sort_by(|a, b| a.chunk_id.as_i16().cmp(&b.chunk_id.as_i16()));
+// Project manifests are never written to, so their sprites are replaced by the split sprites in memory when compiling
+pub fn replace_manifest_sprites(
+    iff_description: &mut iff_description::IffDescription,
+    description_file_path: &std::path::Path,
+    source_directory: &std::path::Path,
+    object_name: &str,
+    variant: Option<&str>,
+) -> anyhow::Result<()> {
+    let split_sprites_directory = split_sprites_directory(source_directory, object_name, variant);
+    if !project::is_project_manifest(description_file_path) || !split_sprites_directory.is_dir() {
+        return Ok(());
+    }
+    replace_sprites(iff_description, read_split_sprites(&split_sprites_directory)?);
+    Ok(())
+}
+
+pub fn update(source_directory: &std::path::Path, object_name: &str, variant: Option<&str>) -> anyhow::Result<()> {
+    let xml_file_path = project::description_file_path(source_directory, object_name);
+    anyhow::ensure!(
+        !project::is_project_manifest(&xml_file_path),
+        "Updating project manifest {} is not supported, the split sprites are used when compiling it",
+        xml_file_path.display()
+    );
+
+    let mut iff_description = iff_description::IffDescription::open(&xml_file_path)
+        .with_context(|| format!("Failed to open xml file {}", xml_file_path.display()))?;
+
+    let new_sprites = read_split_sprites(&split_sprites_directory(source_directory, object_name, variant))?;
+    replace_sprites(&mut iff_description, new_sprites);
 
     iff_description
         .save(&xml_file_path)